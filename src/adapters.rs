@@ -0,0 +1,261 @@
+use crate::Error;
+use crate::reader::{SeqStream, SeqStreamMut};
+use crate::record::{MutRefRecord, OwnedRecord, RefRecord};
+use crate::seq_db::SeqDB;
+
+// Lazy adapters over any SeqStream, in the style of the Iterator adapters. Each adapter is itself a
+// SeqStream, so adapters can be chained and the result passed to anything that takes a SeqStream.
+// The adapters are also SeqStreamMut when the stream under them is.
+//
+// For example, to keep the reads of length at least 50 after the first 1000 and lowercase them:
+//     reader.skip(1000).filter(|rec| rec.seq.len() >= 50).map_mut(|rec| rec.seq.make_ascii_lowercase())
+pub trait SeqStreamExt: SeqStream + Sized{
+
+    // Keeps only the records for which the predicate returns true
+    fn filter<P: FnMut(&RefRecord<'_>) -> bool>(self, predicate: P) -> Filter<Self, P>{
+        Filter{inner: self, predicate, rec: OwnedRecord{head: Vec::new(), seq: Vec::new(), qual: None}}
+    }
+
+    // Modifies each record in place, for example to change the case of the sequence.
+    // The lengths of the head, the sequence and the quality values can not be changed.
+    fn map_mut<F: FnMut(&mut MutRefRecord<'_>)>(self, f: F) -> MapMut<Self, F> where Self: SeqStreamMut{
+        MapMut{inner: self, f}
+    }
+
+    // Returns at most the first n records
+    fn take(self, n: usize) -> Take<Self>{
+        Take{inner: self, remaining: n}
+    }
+
+    // Discards the first n records
+    fn skip(self, n: usize) -> Skip<Self>{
+        Skip{inner: self, remaining: n}
+    }
+
+    // Returns the first record and then every step-th record after it. Panics if step is 0.
+    fn step_by(self, step: usize) -> StepBy<Self>{
+        assert!(step != 0, "step_by with step 0");
+        StepBy{inner: self, step, first: true}
+    }
+
+    // Calls f on each record as it passes through, for example to count or log the records
+    fn inspect<F: FnMut(&RefRecord<'_>)>(self, f: F) -> Inspect<Self, F>{
+        Inspect{inner: self, f}
+    }
+
+    // Reads the remaining records into memory. Not named into_db, which is an inherent method of
+    // the readers and would be called instead of this one on them.
+    fn collect_db(mut self) -> Result<SeqDB, Error>{
+        let mut db = SeqDB::new();
+        while let Some(rec) = self.read_next()?{
+            db.push_record(rec);
+        }
+        db.shrink_to_fit();
+        Ok(db)
+    }
+}
+
+impl<S: SeqStream> SeqStreamExt for S{}
+
+// Copies the record into rec, reusing the allocations of rec
+pub(crate) fn copy_record(src: &RefRecord<'_>, rec: &mut OwnedRecord){
+    rec.head.clear();
+    rec.head.extend_from_slice(src.head);
+    rec.seq.clear();
+    rec.seq.extend_from_slice(src.seq);
+    match (src.qual, rec.qual.as_mut()){
+        (Some(q), Some(buf)) => { buf.clear(); buf.extend_from_slice(q) },
+        (Some(q), None) => rec.qual = Some(q.to_vec()),
+        (None, _) => rec.qual = None,
+    }
+}
+
+// Created by SeqStreamExt::filter. The kept records are copied into a buffer owned by the
+// adapter. The copy is needed because of the borrow checker: it does not allow returning the
+// record borrowed from the inner stream from inside the loop that reads past the rejected
+// records, because the next iteration borrows the stream again. The copy reuses the
+// allocations of the buffer.
+pub struct Filter<S, P>{
+    inner: S,
+    predicate: P,
+    rec: OwnedRecord, // The last kept record
+}
+
+impl<S: SeqStream, P: FnMut(&RefRecord<'_>) -> bool> SeqStream for Filter<S, P>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        loop{
+            match self.inner.read_next()?{
+                None => return Ok(None),
+                Some(rec) => if (self.predicate)(&rec) {
+                    copy_record(&rec, &mut self.rec);
+                    break;
+                },
+            }
+        }
+        Ok(Some(self.rec.as_ref_record()))
+    }
+}
+
+impl<S: SeqStream, P: FnMut(&RefRecord<'_>) -> bool> SeqStreamMut for Filter<S, P>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        match self.read_next()?{
+            None => Ok(None),
+            Some(_) => Ok(Some(self.rec.as_mut_ref_record())),
+        }
+    }
+}
+
+// Created by SeqStreamExt::map_mut
+pub struct MapMut<S, F>{
+    inner: S,
+    f: F,
+}
+
+impl<S: SeqStreamMut, F: FnMut(&mut MutRefRecord<'_>)> SeqStream for MapMut<S, F>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        self.read_next_mut().map(|opt| opt.map(|rec| rec.into_shared_ref()))
+    }
+}
+
+impl<S: SeqStreamMut, F: FnMut(&mut MutRefRecord<'_>)> SeqStreamMut for MapMut<S, F>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        match self.inner.read_next_mut()?{
+            None => Ok(None),
+            Some(mut rec) => {
+                (self.f)(&mut rec);
+                Ok(Some(rec))
+            }
+        }
+    }
+}
+
+// Created by SeqStreamExt::take
+pub struct Take<S>{
+    inner: S,
+    remaining: usize,
+}
+
+impl<S: SeqStream> SeqStream for Take<S>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        if self.remaining == 0 {
+            return Ok(None); // Do not read further from the inner stream
+        }
+        self.remaining -= 1;
+        self.inner.read_next()
+    }
+}
+
+impl<S: SeqStreamMut> SeqStreamMut for Take<S>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        self.inner.read_next_mut()
+    }
+}
+
+// Created by SeqStreamExt::skip
+pub struct Skip<S>{
+    inner: S,
+    remaining: usize, // Records still to be discarded
+}
+
+impl<S: SeqStream> Skip<S>{
+    // Returns false if the stream ended before all of the records were discarded
+    fn discard(&mut self) -> Result<bool, Error>{
+        while self.remaining > 0 {
+            if self.inner.read_next()?.is_none() {
+                return Ok(false);
+            }
+            self.remaining -= 1;
+        }
+        Ok(true)
+    }
+}
+
+impl<S: SeqStream> SeqStream for Skip<S>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        if !self.discard()? {
+            return Ok(None);
+        }
+        self.inner.read_next()
+    }
+}
+
+impl<S: SeqStreamMut> SeqStreamMut for Skip<S>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        if !self.discard()? {
+            return Ok(None);
+        }
+        self.inner.read_next_mut()
+    }
+}
+
+// Created by SeqStreamExt::step_by
+pub struct StepBy<S>{
+    inner: S,
+    step: usize,
+    first: bool, // The first record is returned without discarding any
+}
+
+impl<S: SeqStream> StepBy<S>{
+    // Discards the records between the previous returned record and the next one.
+    // Returns false if the stream ended.
+    fn discard(&mut self) -> Result<bool, Error>{
+        if self.first {
+            self.first = false;
+            return Ok(true);
+        }
+        for _ in 1..self.step {
+            if self.inner.read_next()?.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<S: SeqStream> SeqStream for StepBy<S>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        if !self.discard()? {
+            return Ok(None);
+        }
+        self.inner.read_next()
+    }
+}
+
+impl<S: SeqStreamMut> SeqStreamMut for StepBy<S>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        if !self.discard()? {
+            return Ok(None);
+        }
+        self.inner.read_next_mut()
+    }
+}
+
+// Created by SeqStreamExt::inspect
+pub struct Inspect<S, F>{
+    inner: S,
+    f: F,
+}
+
+impl<S: SeqStream, F: FnMut(&RefRecord<'_>)> SeqStream for Inspect<S, F>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        let rec = self.inner.read_next()?;
+        if let Some(rec) = &rec {
+            (self.f)(rec);
+        }
+        Ok(rec)
+    }
+}
+
+impl<S: SeqStreamMut, F: FnMut(&RefRecord<'_>)> SeqStreamMut for Inspect<S, F>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        let rec = self.inner.read_next_mut()?;
+        if let Some(rec) = &rec {
+            (self.f)(&rec.as_ref_record());
+        }
+        Ok(rec)
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{CompressionType, Error, FileType};
+use crate::parser::{RecordParser, Step};
+use crate::reader::{CasePolicy, ParseError, ReaderBuilder};
+use crate::record::{MutRefRecord, RefRecord};
+use crate::seq_db::SeqDB;
+
+// Reads FASTA or FASTQ records from a tokio stream, for example a socket. This is the asynchronous
+// version of DynamicFastXReader: the compression and the file type are detected from the first bytes
+// in the same way, and the records are parsed by the same code, so both give the same records and errors.
+//
+// read_next is not cancellation safe. If its future is dropped before it completes, for example
+// in a branch of tokio::select! that loses, the reader is left in the middle of a record.
+pub struct AsyncFastXReader{
+    input: Box<dyn AsyncBufRead + Unpin + Send>,
+    filetype: FileType,
+    compression_type: CompressionType,
+    pub(crate) filename: Option<String>, // Only used for error messages
+    parser: RecordParser, // Holds the buffers of the current record
+}
+
+// We wrap the decoders in BufReader because the parser requires buffered reading
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz"))]
+fn buffered<D: tokio::io::AsyncRead + Unpin + Send + 'static>(decoder: D, buffer_size: Option<usize>) -> Box<dyn AsyncBufRead + Unpin + Send>{
+    match buffer_size{
+        Some(capacity) => Box::new(tokio::io::BufReader::with_capacity(capacity, decoder)),
+        None => Box::new(tokio::io::BufReader::new(decoder)),
+    }
+}
+
+// Wraps the input into a decoder for the compression type
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz")), allow(unused_variables))] // Nothing to buffer
+pub(crate) fn decompress<R: AsyncBufRead + Unpin + Send + 'static>(input: R, compression_type: CompressionType, buffer_size: Option<usize>) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error>{
+    match compression_type{
+        #[cfg(feature = "gzip")]
+        CompressionType::Gzip => {
+            let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(input);
+            decoder.multiple_members(true); // Concatenated gzip streams, like BGZF
+            Ok(buffered(decoder, buffer_size))
+        },
+        #[cfg(feature = "zstd")]
+        CompressionType::Zstd => {
+            let mut decoder = async_compression::tokio::bufread::ZstdDecoder::new(input);
+            decoder.multiple_members(true);
+            Ok(buffered(decoder, buffer_size))
+        },
+        #[cfg(feature = "bzip2")]
+        CompressionType::Bzip2 => {
+            let mut decoder = async_compression::tokio::bufread::BzDecoder::new(input);
+            decoder.multiple_members(true);
+            Ok(buffered(decoder, buffer_size))
+        },
+        #[cfg(feature = "xz")]
+        CompressionType::Xz => {
+            let mut decoder = async_compression::tokio::bufread::XzDecoder::new(input);
+            decoder.multiple_members(true);
+            Ok(buffered(decoder, buffer_size))
+        },
+        CompressionType::None => Ok(Box::new(input)),
+        #[allow(unreachable_patterns)] // Reachable only if some compression features are disabled
+        disabled => Err(Error::CompressionDisabled(disabled)),
+    }
+}
+
+impl AsyncFastXReader{
+
+    // New from stream, with automatic detection of the compression and the file type
+    pub async fn new<R: AsyncBufRead + Unpin + Send + 'static>(input: R) -> Result<Self, Error>{
+        ReaderBuilder::new().build_async(input).await
+    }
+
+    pub(crate) fn new_with_format(input: Box<dyn AsyncBufRead + Unpin + Send>, filetype: FileType, compression_type: CompressionType) -> Self{
+        AsyncFastXReader{input, filetype, compression_type, filename: None, parser: RecordParser::new()}
+    }
+
+    fn build_parse_error(&self, message: &str) -> Error{
+        let position = self.parser.position();
+        Error::Parse(
+            ParseError{
+                message: message.to_owned(),
+                filename: self.filename.clone(),
+                filetype: Some(self.filetype),
+                line: position.line,
+                byte_offset: position.line_start,
+                record_index: position.record_index,
+            }
+        )
+    }
+
+    // Read one record from the input.
+    pub async fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        self.read_next_mut().await.map(|opt| opt.map(|rec| rec.into_shared_ref()))
+    }
+
+    // Read one record from the input.
+    pub async fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        self.parser.start_record(self.filetype);
+        loop{
+            let bytes_read = self.input.read_until(b'\n', self.parser.line_buf()).await
+                .map_err(|e| Error::from(e).with_compression(self.compression_type))?;
+            match self.parser.push_line(bytes_read).map_err(|message| self.build_parse_error(&message))?{
+                Step::NeedLine => (),
+                Step::Record => return Ok(Some(self.parser.record())),
+                Step::End => return Ok(None),
+            }
+        }
+    }
+
+    pub async fn into_db(mut self) -> Result<SeqDB, Error>{
+        let mut db = SeqDB::new();
+        while let Some(rec) = self.read_next().await?{
+            db.push_record(rec);
+        }
+        db.shrink_to_fit();
+        Ok(db)
+    }
+
+    pub fn filetype(&self) -> FileType{
+        self.filetype
+    }
+
+    pub fn compression_type(&self) -> CompressionType{
+        self.compression_type
+    }
+
+    // Whether any of the lines read so far ended in "\r\n" (Windows line endings).
+    // The "\r" characters are always stripped from the records.
+    pub fn saw_crlf(&self) -> bool{
+        self.parser.saw_crlf()
+    }
+
+    // Returns true if the input has been consumed completely. If this returns false,
+    // the next call to read_next returns either a record or an error.
+    pub async fn is_at_end(&mut self) -> Result<bool, Error>{
+        // A FASTA header read in the previous iteration may be waiting in the parser
+        if self.parser.has_buffered_line() {
+            return Ok(false);
+        }
+        let bytes = self.input.fill_buf().await.map_err(|e| Error::from(e).with_compression(self.compression_type))?;
+        Ok(bytes.is_empty())
+    }
+
+    // Sets the case conversion applied to the sequences. The default is CasePolicy::Upper.
+    pub fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.parser.set_case_policy(case_policy);
+    }
+
+    // Allows FASTQ records where the sequence and quality values are wrapped over multiple lines.
+    // See StaticFastXReader::set_multiline_fastq.
+    pub fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.parser.set_multiline_fastq(multiline_fastq);
+    }
+
+    // For error messages
+    pub fn set_filepath(&mut self, filepath: &Path){
+        self.filename = Some(filepath.to_string_lossy().into_owned());
+    }
+}
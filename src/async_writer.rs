@@ -0,0 +1,93 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{CompressionType, Error, FileType};
+use crate::record::Record;
+use crate::writer::write_record;
+
+// Records are formatted into the buffer and written to the output when the buffer has this many bytes
+const BUFFER_SIZE: usize = 1 << 16;
+
+// Writes FASTA or FASTQ records to a tokio stream, for example a socket. This is the asynchronous
+// version of DynamicFastXWriter, and formats the records with the same code.
+//
+// The output is buffered internally. Call finish at the end to write the buffered records and the
+// end of the compressed stream. Dropping the writer without calling finish loses the buffered records.
+pub struct AsyncFastXWriter{
+    output: Box<dyn AsyncWrite + Unpin + Send>,
+    filetype: FileType,
+    line_end: &'static [u8], // "\n" or "\r\n"
+    buf: Vec<u8>, // Formatted records that have not been written to the output yet
+}
+
+// Wraps the output into an encoder for the compression type
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz")), allow(unused_variables))] // No levels to set
+pub(crate) fn compress<W: AsyncWrite + Unpin + Send + 'static>(output: W, compression_type: CompressionType, level: i32) -> Result<Box<dyn AsyncWrite + Unpin + Send>, Error>{
+    match compression_type{
+        #[cfg(feature = "gzip")]
+        CompressionType::Gzip => Ok(Box::new(async_compression::tokio::write::GzipEncoder::with_quality(output, async_compression::Level::Precise(level)))),
+        #[cfg(feature = "zstd")]
+        CompressionType::Zstd => Ok(Box::new(async_compression::tokio::write::ZstdEncoder::with_quality(output, async_compression::Level::Precise(level)))),
+        #[cfg(feature = "bzip2")]
+        CompressionType::Bzip2 => Ok(Box::new(async_compression::tokio::write::BzEncoder::with_quality(output, async_compression::Level::Precise(level)))),
+        #[cfg(feature = "xz")]
+        CompressionType::Xz => Ok(Box::new(async_compression::tokio::write::XzEncoder::with_quality(output, async_compression::Level::Precise(level)))),
+        CompressionType::None => Ok(Box::new(output)),
+        #[allow(unreachable_patterns)] // Reachable only if some compression features are disabled
+        disabled => Err(Error::CompressionDisabled(disabled)),
+    }
+}
+
+impl AsyncFastXWriter{
+
+    // Writer without compression. Use WriterBuilder::build_async to compress the output.
+    pub fn new<W: AsyncWrite + Unpin + Send + 'static>(output: W, filetype: FileType) -> Self{
+        Self::new_boxed(Box::new(output), filetype)
+    }
+
+    pub(crate) fn new_boxed(output: Box<dyn AsyncWrite + Unpin + Send>, filetype: FileType) -> Self{
+        Self{output, filetype, line_end: b"\n", buf: Vec::with_capacity(BUFFER_SIZE)}
+    }
+
+    // Write lines ending in "\r\n" instead of "\n".
+    pub fn set_crlf(&mut self, crlf: bool){
+        self.line_end = if crlf {b"\r\n"} else {b"\n"};
+    }
+
+    pub fn filetype(&self) -> FileType{
+        self.filetype
+    }
+
+    pub async fn write<Rec: Record>(&mut self, rec: &Rec) -> Result<(), Error>{
+        let len = self.buf.len();
+        if let Err(e) = write_record(&mut self.buf, self.filetype, self.line_end, rec) {
+            self.buf.truncate(len); // Do not leave half of the record in the output
+            return Err(e.into());
+        }
+        if self.buf.len() >= BUFFER_SIZE {
+            self.write_buffer().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_buffer(&mut self) -> Result<(), Error>{
+        self.output.write_all(&self.buf).await?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    // Writes the buffered records to the output and flushes it. With compression, the records
+    // may stay in the encoder until there is enough data to compress or the writer is finished.
+    pub async fn flush(&mut self) -> Result<(), Error>{
+        self.write_buffer().await?;
+        self.output.flush().await?;
+        Ok(())
+    }
+
+    // Writes the buffered records and the end of the compressed stream, and flushes and shuts
+    // down the output. For a socket, this closes the writing half of the connection.
+    pub async fn finish(mut self) -> Result<(), Error>{
+        self.write_buffer().await?;
+        self.output.shutdown().await?;
+        Ok(())
+    }
+}
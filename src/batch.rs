@@ -0,0 +1,73 @@
+use crate::Error;
+use crate::reader::SeqStream;
+use crate::record::{MutRefRecord, RefRecord};
+use crate::seq_db::{SeqDB, SeqDBIterator};
+
+// A reusable chunk of records stored in contiguous buffers, filled by read_batch on the readers.
+// A batch can be sent to a worker thread and sent back to be filled again. Clearing a batch keeps
+// the allocated memory, so reading into the same batches over and over does not allocate once
+// the buffers have grown to the size of the largest batch.
+#[derive(Debug, Default)]
+pub struct RecordBatch{
+    db: SeqDB,
+}
+
+impl RecordBatch{
+    pub fn new() -> Self{
+        Self{db: SeqDB::new()}
+    }
+
+    // Number of records in the batch
+    pub fn len(&self) -> usize{
+        self.db.sequence_count()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+
+    // Total number of bytes in the headers, sequences and quality values of the batch
+    pub fn byte_size(&self) -> usize{
+        self.db.byte_size()
+    }
+
+    // Removes all records but keeps the allocated memory
+    pub fn clear(&mut self){
+        self.db.clear();
+    }
+
+    pub fn get(&self, index: usize) -> RefRecord<'_>{
+        self.db.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> MutRefRecord<'_>{
+        self.db.get_mut(index)
+    }
+
+    pub fn iter(&self) -> SeqDBIterator<'_>{
+        self.db.iter()
+    }
+
+    pub fn push_record<R: crate::record::Record>(&mut self, rec: R){
+        self.db.push_record(rec);
+    }
+
+    pub fn into_db(self) -> SeqDB{
+        self.db
+    }
+}
+
+// Clears the batch and fills it with records from the stream until it has max_records records or
+// at least max_bytes bytes. A batch always gets at least one record if the stream is not at the end,
+// even if the record alone is larger than max_bytes or max_records is 0. Returns the number of
+// records read, which is 0 only at the end of the stream.
+pub(crate) fn fill_batch<S: SeqStream + ?Sized>(stream: &mut S, batch: &mut RecordBatch, max_records: usize, max_bytes: usize) -> Result<usize, Error>{
+    batch.clear();
+    while batch.is_empty() || (batch.len() < max_records && batch.byte_size() < max_bytes){
+        match stream.read_next()?{
+            Some(rec) => batch.push_record(rec),
+            None => break,
+        }
+    }
+    Ok(batch.len())
+}
@@ -0,0 +1,419 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+#[cfg(feature = "bgzf-parallel")]
+use std::collections::BTreeMap;
+#[cfg(feature = "bgzf-parallel")]
+use std::io::BufRead;
+#[cfg(feature = "bgzf-parallel")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "bgzf-parallel")]
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(feature = "bgzf-parallel")]
+use std::thread::JoinHandle;
+
+use ex::fs::File;
+use flate2::{Crc, Decompress, FlushDecompress, Status};
+
+use crate::Error;
+
+// BGZF (blocked gzip, as written by bgzip and samtools) is a series of gzip members of at most
+// 64 KiB each. The compressed size of each member is stored in a "BC" field of the gzip header,
+// so the members can be cut out of the stream without decompressing them, and decompressed in parallel.
+
+const GZIP_HEADER_LEN: usize = 12; // Fixed part of the header up to and including XLEN
+const GZIP_TRAILER_LEN: usize = 8; // CRC32 and ISIZE
+const MAX_BLOCK_DATA_LEN: usize = 1 << 16; // Maximum uncompressed size of a BGZF block
+
+// Returns true if the bytes start with a gzip header that has the BGZF extra field.
+// Needs the first 18 bytes of the stream to say yes.
+pub fn is_bgzf(header: &[u8]) -> bool{
+    block_size(header).is_some()
+}
+
+// Parses the total size of the BGZF block starting at the given header,
+// or returns None if the header is not a BGZF header or is incomplete.
+fn block_size(header: &[u8]) -> Option<usize>{
+    if header.len() < GZIP_HEADER_LEN || header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 || header[3] & 4 == 0 {
+        return None; // Not gzip with the deflate method and the FEXTRA flag
+    }
+    let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+    let mut extra = header.get(GZIP_HEADER_LEN..GZIP_HEADER_LEN + xlen)?;
+    // The extra field is a list of subfields: SI1, SI2, SLEN (2 bytes), data (SLEN bytes)
+    while extra.len() >= 4 {
+        let slen = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra[0] == b'B' && extra[1] == b'C' && slen == 2 && extra.len() >= 6 {
+            let bsize = u16::from_le_bytes([extra[4], extra[5]]) as usize;
+            return Some(bsize + 1);
+        }
+        extra = extra.get(4 + slen..)?;
+    }
+    None
+}
+
+// Reads the next complete BGZF block from the input into buf without decompressing it.
+// Returns false at the end of the input.
+pub(crate) fn read_block<R: Read>(input: &mut R, buf: &mut Vec<u8>) -> std::io::Result<bool>{
+    buf.clear();
+    buf.resize(GZIP_HEADER_LEN, 0);
+    // Read the first byte separately to tell the clean end of the input from a truncated header
+    loop {
+        match input.read(&mut buf[..1]){
+            Ok(0) => return Ok(false),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    input.read_exact(&mut buf[1..])?;
+    let xlen = u16::from_le_bytes([buf[10], buf[11]]) as usize;
+    buf.resize(GZIP_HEADER_LEN + xlen, 0);
+    input.read_exact(&mut buf[GZIP_HEADER_LEN..])?;
+
+    let size = match block_size(buf){
+        Some(size) if size >= buf.len() + GZIP_TRAILER_LEN => size,
+        _ => return Err(std::io::Error::new(ErrorKind::InvalidData, "Corrupt BGZF stream: invalid block header")),
+    };
+    let header_len = buf.len();
+    buf.resize(size, 0);
+    input.read_exact(&mut buf[header_len..])?;
+    Ok(true)
+}
+
+// Decompresses one complete BGZF block into out, checking the length and the CRC from the trailer.
+pub(crate) fn decompress_block(decompressor: &mut Decompress, block: &[u8], out: &mut Vec<u8>) -> std::io::Result<()>{
+    let invalid = |msg: &str| std::io::Error::new(ErrorKind::InvalidData, format!("Corrupt BGZF block: {}", msg));
+
+    let xlen = u16::from_le_bytes([block[10], block[11]]) as usize;
+    let data_start = GZIP_HEADER_LEN + xlen;
+    if block.len() < data_start + GZIP_TRAILER_LEN {
+        return Err(invalid("block size is smaller than the header"));
+    }
+    let trailer = &block[block.len() - GZIP_TRAILER_LEN..];
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let uncompressed_len = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]) as usize;
+
+    if uncompressed_len > MAX_BLOCK_DATA_LEN {
+        // Checked before reserving, so that a corrupt trailer can not make us allocate gigabytes
+        return Err(invalid("uncompressed size in the trailer is larger than 64 KiB"));
+    }
+
+    out.clear();
+    out.reserve(uncompressed_len);
+    decompressor.reset(false);
+    let status = decompressor.decompress_vec(&block[data_start..block.len() - GZIP_TRAILER_LEN], out, FlushDecompress::Finish)?;
+    if status != Status::StreamEnd || out.len() != uncompressed_len {
+        return Err(invalid("length does not match the trailer"));
+    }
+    let mut actual_crc = Crc::new();
+    actual_crc.update(out);
+    if actual_crc.sum() != crc {
+        return Err(invalid("CRC mismatch"));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bgzf-parallel")]
+struct Job{
+    block_index: u64,
+    compressed: Vec<u8>,
+    decompressed: Vec<u8>, // Reused buffer for the output
+}
+
+#[cfg(feature = "bgzf-parallel")]
+struct JobResult{
+    block_index: u64,
+    compressed: Vec<u8>, // Returned for reuse
+    decompressed: std::io::Result<Vec<u8>>,
+}
+
+// Decompresses a BGZF stream on a pool of worker threads. The calling thread cuts the blocks out of
+// the input and the workers decompress them. The output comes out in the original order.
+// Decompression runs ahead of the consumer by at most a fixed number of blocks per thread.
+#[cfg(feature = "bgzf-parallel")]
+pub struct BgzfReader<R: Read>{
+    input: R,
+    input_ended: bool,
+    job_sender: Option<Sender<Job>>,
+    result_receiver: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+    max_in_flight: usize,
+    n_in_flight: usize,
+    n_sent: u64, // Number of blocks sent to the workers
+    next_block: u64, // Index of the block after the current one
+    finished: BTreeMap<u64, std::io::Result<Vec<u8>>>, // Blocks that were decompressed before they are needed
+    free_compressed: Vec<Vec<u8>>,
+    free_decompressed: Vec<Vec<u8>>,
+    current: Vec<u8>, // The decompressed block being read
+    pos: usize, // Position in current
+}
+
+#[cfg(feature = "bgzf-parallel")]
+impl<R: Read> BgzfReader<R>{
+
+    // The number of threads is at least 1
+    pub fn new(input: R, n_threads: usize) -> Self{
+        let n_threads = n_threads.max(1);
+        let (job_sender, job_receiver) = channel::<Job>();
+        let (result_sender, result_receiver) = channel::<JobResult>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..n_threads).map(|_| {
+            let job_receiver = Arc::clone(&job_receiver);
+            let result_sender = result_sender.clone();
+            std::thread::spawn(move || decompress_blocks(&job_receiver, result_sender))
+        }).collect();
+
+        Self{
+            input,
+            input_ended: false,
+            job_sender: Some(job_sender),
+            result_receiver,
+            workers,
+            max_in_flight: 4 * n_threads,
+            n_in_flight: 0,
+            n_sent: 0,
+            next_block: 0,
+            finished: BTreeMap::new(),
+            free_compressed: Vec::new(),
+            free_decompressed: Vec::new(),
+            current: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    // Sends blocks to the workers until enough are in flight or the input ends. An error
+    // in the input is stored as the next block, so that the blocks before it are still returned.
+    fn send_jobs(&mut self) -> std::io::Result<()>{
+        while !self.input_ended && self.n_in_flight < self.max_in_flight {
+            let mut compressed = self.free_compressed.pop().unwrap_or_default();
+            match read_block(&mut self.input, &mut compressed){
+                Ok(true) => (),
+                Ok(false) => {
+                    self.input_ended = true;
+                    break;
+                },
+                Err(e) => {
+                    self.finished.insert(self.n_sent, Err(e));
+                    self.n_sent += 1;
+                    self.input_ended = true;
+                    break;
+                }
+            }
+            let job = Job{block_index: self.n_sent, compressed, decompressed: self.free_decompressed.pop().unwrap_or_default()};
+            let sent = self.job_sender.as_ref().map(|sender| sender.send(job));
+            if !matches!(sent, Some(Ok(()))) {
+                return Err(std::io::Error::other("BGZF worker threads have stopped"));
+            }
+            self.n_sent += 1;
+            self.n_in_flight += 1;
+        }
+        Ok(())
+    }
+
+    // Makes the next decompressed block the current one. Returns false at the end of the stream.
+    fn next_block(&mut self) -> std::io::Result<bool>{
+        self.send_jobs()?;
+        if self.next_block == self.n_sent {
+            return Ok(false); // Everything read from the input has been consumed
+        }
+        while !self.finished.contains_key(&self.next_block) {
+            let result = self.result_receiver.recv().map_err(|_| std::io::Error::other("BGZF worker threads have stopped"))?;
+            self.n_in_flight -= 1;
+            self.free_compressed.push(result.compressed);
+            self.finished.insert(result.block_index, result.decompressed);
+        }
+        let block = self.finished.remove(&self.next_block).unwrap(); // Was just checked to exist
+        self.next_block += 1;
+        let previous = std::mem::replace(&mut self.current, block?);
+        self.free_decompressed.push(previous);
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "bgzf-parallel")]
+fn decompress_blocks(jobs: &Mutex<Receiver<Job>>, results: Sender<JobResult>){
+    let mut decompressor = Decompress::new(false);
+    loop {
+        // The lock guard is dropped at the end of the statement
+        let job = match jobs.lock().map(|receiver| receiver.recv()){
+            Ok(Ok(job)) => job,
+            _ => return, // The reader has been dropped
+        };
+        let mut decompressed = job.decompressed;
+        let result = decompress_block(&mut decompressor, &job.compressed, &mut decompressed).map(|_| decompressed);
+        if results.send(JobResult{block_index: job.block_index, compressed: job.compressed, decompressed: result}).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(feature = "bgzf-parallel")]
+impl<R: Read> Read for BgzfReader<R>{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "bgzf-parallel")]
+impl<R: Read> BufRead for BgzfReader<R>{
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        // Loop because blocks can be empty, like the end-of-file marker block
+        while self.pos == self.current.len() {
+            if !self.next_block()? {
+                break;
+            }
+        }
+        Ok(&self.current[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.current.len());
+    }
+}
+
+#[cfg(feature = "bgzf-parallel")]
+impl<R: Read> Drop for BgzfReader<R>{
+    fn drop(&mut self) {
+        self.job_sender = None; // Closes the job channel so that the workers stop
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// The .gzi index written by bgzip -i: the compressed and uncompressed offsets of the starts of the blocks,
+// which allows seeking to an uncompressed position. The first block, at offsets (0, 0), is not stored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GziIndex{
+    entries: Vec<(u64, u64)>, // (compressed offset, uncompressed offset), sorted
+}
+
+impl GziIndex{
+
+    // Builds the index by reading through the block headers of a BGZF stream. Only the
+    // sizes of the blocks are read, so this is much faster than decompressing the stream.
+    pub fn build<R: Read>(mut input: R) -> Result<Self, Error>{
+        let mut entries = Vec::new();
+        let mut block = Vec::new();
+        let (mut compressed_offset, mut uncompressed_offset) = (0_u64, 0_u64);
+        while read_block(&mut input, &mut block).map_err(Error::Decompression)? {
+            if compressed_offset > 0 {
+                entries.push((compressed_offset, uncompressed_offset));
+            }
+            let isize_bytes = &block[block.len() - 4..];
+            compressed_offset += block.len() as u64;
+            uncompressed_offset += u32::from_le_bytes([isize_bytes[0], isize_bytes[1], isize_bytes[2], isize_bytes[3]]) as u64;
+        }
+        Ok(Self{entries})
+    }
+
+    // Reads the binary .gzi format: the number of entries and then the pairs of offsets, all as little-endian u64
+    pub fn read<R: Read>(mut input: R) -> Result<Self, Error>{
+        let mut read_u64 = || -> Result<u64, Error> {
+            let mut bytes = [0_u8; 8];
+            input.read_exact(&mut bytes).map_err(|e| match e.kind(){
+                ErrorKind::UnexpectedEof => Error::Index("Truncated .gzi index".to_string()),
+                _ => Error::Io(e),
+            })?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let n_entries = read_u64()?;
+        let mut entries = Vec::new();
+        for _ in 0..n_entries {
+            let compressed_offset = read_u64()?;
+            let uncompressed_offset = read_u64()?;
+            entries.push((compressed_offset, uncompressed_offset));
+        }
+        if entries.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::Index("The offsets in the .gzi index are not increasing".to_string()));
+        }
+        Ok(Self{entries})
+    }
+
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Self::read(std::io::BufReader::new(File::open(filepath)?))
+    }
+
+    pub fn write<W: Write>(&self, mut output: W) -> Result<(), Error>{
+        output.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (compressed_offset, uncompressed_offset) in self.entries.iter() {
+            output.write_all(&compressed_offset.to_le_bytes())?;
+            output.write_all(&uncompressed_offset.to_le_bytes())?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, filepath: &P) -> Result<(), Error>{
+        self.write(std::io::BufWriter::new(File::create(filepath)?))
+    }
+
+    // Returns the (compressed, uncompressed) offsets of the last block that starts at or before
+    // the given uncompressed position.
+    pub fn locate(&self, uncompressed_pos: u64) -> (u64, u64){
+        match self.entries.partition_point(|&(_, uncompressed_offset)| uncompressed_offset <= uncompressed_pos){
+            0 => (0, 0),
+            i => self.entries[i - 1],
+        }
+    }
+}
+
+// Reads ranges of uncompressed bytes from a seekable BGZF stream. The last decompressed block is kept,
+// so reading ranges that are close to each other in increasing order does not decompress any block twice.
+pub(crate) struct BgzfRangeReader<R: Read + Seek>{
+    input: R,
+    gzi: GziIndex,
+    decompressor: Decompress,
+    compressed: Vec<u8>,
+    block: Vec<u8>, // The last decompressed block
+    block_start: u64, // Uncompressed offset of the start of block
+    input_after_block: bool, // Whether the input is positioned right after the last decompressed block
+}
+
+impl<R: Read + Seek> BgzfRangeReader<R>{
+    pub(crate) fn new(input: R, gzi: GziIndex) -> Self{
+        Self{input, gzi, decompressor: Decompress::new(false), compressed: Vec::new(), block: Vec::new(), block_start: 0, input_after_block: false}
+    }
+
+    // Appends the uncompressed bytes in the range [start, end) to out
+    pub(crate) fn read_range(&mut self, start: u64, end: u64, out: &mut Vec<u8>) -> Result<(), Error>{
+        let mut pos = start;
+        while pos < end {
+            if pos < self.block_start || pos >= self.block_start + self.block.len() as u64 {
+                self.load_block_containing(pos)?;
+            }
+            let from = (pos - self.block_start) as usize;
+            let to = (end.min(self.block_start + self.block.len() as u64) - self.block_start) as usize;
+            out.extend_from_slice(&self.block[from..to]);
+            pos = self.block_start + to as u64;
+        }
+        Ok(())
+    }
+
+    fn load_block_containing(&mut self, pos: u64) -> Result<(), Error>{
+        let (compressed_offset, uncompressed_offset) = self.gzi.locate(pos);
+        // Continue from the current position if it is not behind the block given by the index
+        let sequential = self.input_after_block && uncompressed_offset <= self.block_start && self.block_start <= pos;
+        if !sequential {
+            self.input_after_block = false;
+            self.input.seek(SeekFrom::Start(compressed_offset))?;
+            self.block.clear();
+            self.block_start = uncompressed_offset;
+        }
+        while pos >= self.block_start + self.block.len() as u64 {
+            self.block_start += self.block.len() as u64;
+            self.block.clear();
+            self.input_after_block = false;
+            if !read_block(&mut self.input, &mut self.compressed).map_err(Error::Decompression)? {
+                return Err(Error::Decompression(std::io::Error::new(ErrorKind::UnexpectedEof, "BGZF stream ended before the requested position")));
+            }
+            decompress_block(&mut self.decompressor, &self.compressed, &mut self.block).map_err(Error::Decompression)?;
+            self.input_after_block = true;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+use crate::paired::PairError;
+use crate::reader::ParseError;
+use crate::CompressionType;
+
+// The error type returned by all fallible functions of the crate.
+// Callers can match on the variant to tell apart the different kinds of failures.
+#[derive(Debug)]
+pub enum Error{
+    // Reading from or writing to the underlying stream failed.
+    Io(std::io::Error),
+
+    // The input is not valid FASTA or FASTQ.
+    Parse(ParseError),
+
+    // The compressed input stream is corrupt or truncated.
+    Decompression(std::io::Error),
+
+    // The file format or compression type could not be determined or is not supported.
+    Format(String),
+
+    // The mates of paired-end reads do not match.
+    Pair(PairError),
+
+    // An index file is malformed, or a requested sequence or region is not in the index.
+    Index(String),
+
+    // The input or output uses a compression type whose cargo feature is not enabled.
+    CompressionDisabled(CompressionType),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error{
+    // Errors from a decompressing stream come out as plain I/O errors. This reclassifies
+    // them as decompression errors if the stream was compressed.
+    pub(crate) fn with_compression(self, compression_type: CompressionType) -> Self{
+        match (self, compression_type){
+            (Error::Io(e), compression_type) if compression_type != CompressionType::None && is_decompression_error_kind(e.kind()) => Error::Decompression(e),
+            (other, _) => other,
+        }
+    }
+}
+
+fn is_decompression_error_kind(kind: std::io::ErrorKind) -> bool{
+    matches!(kind, std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput | std::io::ErrorKind::UnexpectedEof)
+}
+
+impl fmt::Display for Error{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Parse(e) => write!(f, "Parse error: {}", e),
+            Error::Decompression(e) => write!(f, "Decompression error: {}", e),
+            Error::Format(msg) => write!(f, "Format error: {}", msg),
+            Error::Pair(e) => write!(f, "Paired-end error: {}", e),
+            Error::Index(msg) => write!(f, "Index error: {}", msg),
+            Error::CompressionDisabled(c) => write!(f, "Support for {:?} compression is not enabled. Enable the \"{}\" feature of jseqio.", c, c.feature_name()),
+        }
+    }
+}
+
+impl std::error::Error for Error{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self{
+            Error::Io(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Decompression(e) => Some(e),
+            Error::Format(_) => None,
+            Error::Pair(e) => Some(e),
+            Error::Index(_) => None,
+            Error::CompressionDisabled(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error{
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+// The file streams from the ex crate include the filename in their error messages.
+// We keep the message by wrapping the error into a std::io::Error of the same kind.
+impl From<ex::io::Error> for Error{
+    fn from(e: ex::io::Error) -> Self {
+        Error::Io(std::io::Error::new(e.kind(), e))
+    }
+}
+
+impl From<ParseError> for Error{
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<PairError> for Error{
+    fn from(e: PairError) -> Self {
+        Error::Pair(e)
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use ex::fs::File;
+
+use crate::Error;
+#[cfg(feature = "gzip")]
+use crate::bgzf::{BgzfRangeReader, GziIndex};
+use crate::record::OwnedRecord;
+
+// One line of a samtools .fai index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaiEntry{
+    pub name: String, // The header up to the first whitespace
+    pub length: u64, // Number of bases
+    pub offset: u64, // Byte offset of the first base in the uncompressed file
+    pub line_bases: u64, // Number of bases on each line, except possibly the last
+    pub line_width: u64, // Number of bytes on each line including the line end, except possibly the last
+}
+
+impl FaiEntry{
+    // Byte offset of a 0-based base position in the uncompressed file
+    pub fn byte_offset(&self, pos: u64) -> u64{
+        if self.line_bases == 0 {
+            return self.offset; // Empty sequence
+        }
+        self.offset + (pos / self.line_bases) * self.line_width + pos % self.line_bases
+    }
+}
+
+// A FASTA index in the samtools .fai format. All lines of a sequence except the last must have
+// the same length, or the sequence can not be indexed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FastaIndex{
+    entries: Vec<FaiEntry>,
+    name_to_index: HashMap<String, usize>,
+}
+
+impl FastaIndex{
+
+    // Returns Error::Index if the names are not unique or the line layout of an entry is impossible
+    pub fn new(entries: Vec<FaiEntry>) -> Result<Self, Error>{
+        let mut name_to_index = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if (entry.line_bases == 0 && entry.length > 0) || entry.line_width < entry.line_bases {
+                return Err(Error::Index(format!("Invalid line length or width for sequence {}", entry.name)));
+            }
+            if name_to_index.insert(entry.name.clone(), i).is_some() {
+                return Err(Error::Index(format!("Duplicate sequence name {}", entry.name)));
+            }
+        }
+        Ok(Self{entries, name_to_index})
+    }
+
+    // Builds the index by reading through uncompressed FASTA data
+    pub fn build<R: BufRead>(mut input: R) -> Result<Self, Error>{
+        let mut entries = Vec::<FaiEntry>::new();
+        let mut line = Vec::<u8>::new();
+        let mut offset = 0_u64; // Byte offset of the start of the current line
+        let mut saw_short_line = false; // Whether the current sequence has had a line shorter than the first one
+        loop {
+            line.clear();
+            let width = input.read_until(b'\n', &mut line)? as u64;
+            if width == 0 {
+                break;
+            }
+            let mut content = line.as_slice();
+            while let [rest @ .., b'\n' | b'\r'] = content {
+                content = rest;
+            }
+
+            if let Some(header) = content.strip_prefix(b">") {
+                let name = header.split(|c| c.is_ascii_whitespace()).next().unwrap_or(header);
+                entries.push(FaiEntry{name: String::from_utf8_lossy(name).into_owned(), length: 0, offset: offset + width, line_bases: 0, line_width: 0});
+                saw_short_line = false;
+            } else if let Some(entry) = entries.last_mut() {
+                let bases = content.len() as u64;
+                if entry.line_bases == 0 && !saw_short_line {
+                    // First line of the sequence
+                    entry.line_bases = bases;
+                    entry.line_width = width;
+                    saw_short_line = bases == 0;
+                } else if saw_short_line && bases > 0 {
+                    return Err(Error::Index(format!("Different line lengths in sequence {}", entry.name)));
+                } else if bases != entry.line_bases || width != entry.line_width {
+                    if bases > entry.line_bases {
+                        return Err(Error::Index(format!("Different line lengths in sequence {}", entry.name)));
+                    }
+                    saw_short_line = true; // Only the last line may be shorter
+                }
+                entry.length += bases;
+            } else if !content.is_empty() {
+                return Err(Error::Index("FASTA file does not start with a header line".to_string()));
+            }
+            offset += width;
+        }
+        Self::new(entries)
+    }
+
+    // Builds the index for a FASTA file, which can be uncompressed or BGZF-compressed
+    pub fn build_from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Self::build(open_indexable_stream(filepath)?)
+    }
+
+    // Reads an index in the .fai format
+    pub fn read<R: BufRead>(input: R) -> Result<Self, Error>{
+        let mut entries = Vec::new();
+        for (line_index, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let number = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+            match (number(1), number(2), number(3), number(4)){
+                (Some(length), Some(offset), Some(line_bases), Some(line_width)) if fields.len() == 5 => {
+                    entries.push(FaiEntry{name: fields[0].to_string(), length, offset, line_bases, line_width});
+                },
+                _ => return Err(Error::Index(format!("Invalid .fai line {}: {}", line_index + 1, line))),
+            }
+        }
+        Self::new(entries)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Self::read(BufReader::new(File::open(filepath)?))
+    }
+
+    // Writes the index in the .fai format
+    pub fn write<W: Write>(&self, mut output: W) -> Result<(), Error>{
+        for e in self.entries.iter() {
+            writeln!(output, "{}\t{}\t{}\t{}\t{}", e.name, e.length, e.offset, e.line_bases, e.line_width)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, filepath: &P) -> Result<(), Error>{
+        self.write(std::io::BufWriter::new(File::create(filepath)?))
+    }
+
+    pub fn entries(&self) -> &[FaiEntry]{
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FaiEntry>{
+        self.name_to_index.get(name).map(|&i| &self.entries[i])
+    }
+}
+
+// A region of a sequence with 0-based half-open coordinates. An end of None means the end of the sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region{
+    pub name: String,
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl Region{
+    // Parses a samtools-style region "name", "name:start" or "name:start-end", where start and end
+    // are 1-based and inclusive. The numbers may contain commas, like "chr1:1,000,000-2,000,000".
+    pub fn parse(region: &str) -> Result<Self, Error>{
+        let invalid = || Error::Index(format!("Invalid region: {}", region));
+        let parse_number = |s: &str| s.replace(',', "").parse::<u64>().map_err(|_| invalid());
+
+        let (name, range) = match region.rsplit_once(':'){
+            Some((name, range)) => (name, range),
+            None => return Ok(Self{name: region.to_string(), start: 0, end: None}),
+        };
+        let (start, end) = match range.split_once('-'){
+            Some((start, end)) => (parse_number(start)?, Some(parse_number(end)?)),
+            None => (parse_number(range)?, None),
+        };
+        if name.is_empty() || start == 0 || end.is_some_and(|end| end < start) {
+            return Err(invalid());
+        }
+        Ok(Self{name: name.to_string(), start: start - 1, end})
+    }
+}
+
+// Fetches regions of sequences from an indexed FASTA file. The file can be uncompressed,
+// or compressed with bgzip, in which case the .gzi index is used to find the blocks.
+// The sequences are returned as they are in the file, without case conversion.
+pub struct IndexedFastaReader<R: Read + Seek = File>{
+    input: RangeReader<R>,
+    index: FastaIndex,
+    raw_buf: Vec<u8>, // Bytes of a region including the line ends
+}
+
+impl IndexedFastaReader<File>{
+    // Opens a FASTA file with its index at filepath.fai, or filepath.gzi as well for BGZF-compressed files.
+    // An index that does not exist is built by reading the file, but it is not written to disk.
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        let fai_path = with_added_extension(filepath.as_ref(), "fai");
+        let index = if fai_path.exists() { FastaIndex::from_file(&fai_path)? } else { FastaIndex::build_from_file(filepath)? };
+
+        Ok(Self{input: open_range_reader(filepath)?, index, raw_buf: Vec::new()})
+    }
+}
+
+impl<R: Read + Seek> IndexedFastaReader<R>{
+
+    // Reader for uncompressed FASTA
+    pub fn new(input: R, index: FastaIndex) -> Self{
+        Self{input: RangeReader::Plain(input), index, raw_buf: Vec::new()}
+    }
+
+    // Reader for BGZF-compressed FASTA
+    #[cfg(feature = "gzip")]
+    pub fn new_bgzf(input: R, index: FastaIndex, gzi: GziIndex) -> Self{
+        Self{input: RangeReader::Bgzf(BgzfRangeReader::new(input, gzi)), index, raw_buf: Vec::new()}
+    }
+
+    pub fn index(&self) -> &FastaIndex{
+        &self.index
+    }
+
+    // Clears buf and writes the bases in the 0-based half-open range [start, end) of the sequence into it.
+    // An end past the end of the sequence is clamped to the end, like in samtools.
+    pub fn fetch_into(&mut self, name: &str, start: u64, end: u64, buf: &mut Vec<u8>) -> Result<(), Error>{
+        buf.clear();
+        let entry = self.index.get(name).ok_or_else(|| Error::Index(format!("Sequence {} not found in the index", name)))?;
+        let end = end.min(entry.length);
+        if start > end {
+            return Err(Error::Index(format!("Region {}:{}-{} starts after the end of the sequence of length {}", name, start + 1, end, entry.length)));
+        }
+        if start == end {
+            return Ok(());
+        }
+        let (byte_start, byte_end) = (entry.byte_offset(start), entry.byte_offset(end - 1) + 1);
+
+        self.raw_buf.clear();
+        self.input.read_range(byte_start, byte_end, &mut self.raw_buf)?;
+        buf.extend(self.raw_buf.iter().filter(|&&c| c != b'\n' && c != b'\r'));
+        if buf.len() as u64 != end - start {
+            return Err(Error::Index(format!("The index does not match the file at sequence {}", name)));
+        }
+        Ok(())
+    }
+
+    // Returns the bases in the 0-based half-open range [start, end) of the sequence. The header of
+    // the record is the region in the samtools format "name:start-end" with 1-based coordinates.
+    pub fn fetch(&mut self, name: &str, start: u64, end: u64) -> Result<OwnedRecord, Error>{
+        let mut seq = Vec::new();
+        self.fetch_into(name, start, end, &mut seq)?;
+        let head = format!("{}:{}-{}", name, start + 1, start + seq.len() as u64).into_bytes();
+        Ok(OwnedRecord{head, seq, qual: None})
+    }
+
+    // Returns the bases of a samtools-style region "name", "name:start" or "name:start-end" with
+    // 1-based inclusive coordinates. The header of the record is the region as given. A name
+    // that contains ':' is first looked up as a whole, like in samtools.
+    pub fn fetch_region(&mut self, region: &str) -> Result<OwnedRecord, Error>{
+        let region_parsed = match self.index.get(region){
+            Some(_) => Region{name: region.to_string(), start: 0, end: None},
+            None => Region::parse(region)?,
+        };
+        let mut seq = Vec::new();
+        self.fetch_into(&region_parsed.name, region_parsed.start, region_parsed.end.unwrap_or(u64::MAX), &mut seq)?;
+        Ok(OwnedRecord{head: region.as_bytes().to_vec(), seq, qual: None})
+    }
+}
+
+// Reads ranges of uncompressed bytes from a plain or BGZF-compressed file
+pub(crate) enum RangeReader<R: Read + Seek>{
+    Plain(R),
+    #[cfg(feature = "gzip")]
+    Bgzf(BgzfRangeReader<R>),
+}
+
+impl<R: Read + Seek> RangeReader<R>{
+    // Appends the uncompressed bytes in the range [start, end) to out
+    pub(crate) fn read_range(&mut self, start: u64, end: u64, out: &mut Vec<u8>) -> Result<(), Error>{
+        match self{
+            RangeReader::Plain(input) => {
+                input.seek(SeekFrom::Start(start))?;
+                let old_len = out.len();
+                out.resize(old_len + (end - start) as usize, 0);
+                input.read_exact(&mut out[old_len..])?;
+                Ok(())
+            },
+            #[cfg(feature = "gzip")]
+            RangeReader::Bgzf(input) => input.read_range(start, end, out),
+        }
+    }
+}
+
+// Opens an uncompressed or BGZF-compressed file. Returns the file and whether it is BGZF.
+// Plain gzip can not be read at random positions, so it is an error.
+fn open_indexable<P: AsRef<Path>>(filepath: &P) -> Result<(BufReader<File>, bool), Error>{
+    let mut input = BufReader::new(File::open(filepath)?);
+    let header = input.fill_buf()?;
+    if !header.starts_with(&[0x1f, 0x8b]) {
+        return Ok((input, false));
+    }
+    #[cfg(feature = "gzip")]
+    if crate::bgzf::is_bgzf(header) {
+        return Ok((input, true));
+    }
+    if cfg!(feature = "gzip") {
+        Err(Error::Format(format!("{} is compressed with gzip but not with bgzip, so it can not be indexed", filepath.as_ref().display())))
+    } else {
+        Err(Error::CompressionDisabled(crate::CompressionType::Gzip))
+    }
+}
+
+// Opens an uncompressed or BGZF-compressed file for reading the uncompressed data from the start
+pub(crate) fn open_indexable_stream<P: AsRef<Path>>(filepath: &P) -> Result<Box<dyn BufRead>, Error>{
+    match open_indexable(filepath)?{
+        #[cfg(feature = "gzip")]
+        (input, true) => Ok(Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(input)))),
+        (input, _) => Ok(Box::new(input)),
+    }
+}
+
+// Opens an uncompressed or BGZF-compressed file for reading ranges of the uncompressed data.
+// For BGZF, the .gzi index is loaded from filepath.gzi, or built if the file does not exist.
+pub(crate) fn open_range_reader<P: AsRef<Path>>(filepath: &P) -> Result<RangeReader<File>, Error>{
+    match open_indexable(filepath)?{
+        #[cfg(feature = "gzip")]
+        (input, true) => {
+            let gzi_path = with_added_extension(filepath.as_ref(), "gzi");
+            let gzi = if gzi_path.exists() { GziIndex::from_file(&gzi_path)? } else { GziIndex::build(BufReader::new(File::open(filepath)?))? };
+            Ok(RangeReader::Bgzf(BgzfRangeReader::new(input.into_inner(), gzi)))
+        },
+        (input, _) => Ok(RangeReader::Plain(input.into_inner())),
+    }
+}
+
+// Returns the path with ".ext" appended, for example "genome.fa.gz" -> "genome.fa.gz.fai"
+pub(crate) fn with_added_extension(path: &Path, ext: &str) -> PathBuf{
+    let mut s = path.as_os_str().to_owned();
+    s.push(".");
+    s.push(ext);
+    PathBuf::from(s)
+}
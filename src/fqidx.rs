@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::path::Path;
+
+use ex::fs::File;
+
+use crate::{Error, FileType};
+#[cfg(feature = "gzip")]
+use crate::bgzf::{BgzfRangeReader, GziIndex};
+use crate::faidx::{open_indexable_stream, open_range_reader, with_added_extension, RangeReader};
+use crate::reader::{CasePolicy, ReaderBuilder};
+use crate::record::OwnedRecord;
+
+// One line of a samtools fqidx .fai index. The quality values have the same line layout as the sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FqiEntry{
+    pub name: String, // The header up to the first whitespace
+    pub length: u64, // Number of bases
+    pub offset: u64, // Byte offset of the first base in the uncompressed file
+    pub line_bases: u64, // Number of bases on each line, except possibly the last
+    pub line_width: u64, // Number of bytes on each line including the line end, except possibly the last
+    pub qual_offset: u64, // Byte offset of the first quality value in the uncompressed file
+}
+
+impl FqiEntry{
+    // Byte offset of the end of the quality values, not including the final line end
+    fn qual_end(&self) -> u64{
+        match self.length{
+            0 => self.qual_offset,
+            n => self.qual_offset + ((n - 1) / self.line_bases) * self.line_width + (n - 1) % self.line_bases + 1,
+        }
+    }
+}
+
+// A FASTQ index in the samtools fqidx .fai format. All sequence and quality lines of a record
+// except the last must have the same length, or the record can not be indexed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FastqIndex{
+    entries: Vec<FqiEntry>,
+    name_to_index: HashMap<String, usize>,
+}
+
+// Where the index builder is within a record
+enum BuildState{
+    Header, // Expecting the header line of the next record
+    Sequence{saw_short_line: bool}, // Reading sequence lines until the + line
+    Quality{remaining: u64}, // Reading quality lines until there are as many quality values as bases
+}
+
+impl FastqIndex{
+
+    // Returns Error::Index if the names are not unique or the line layout of an entry is impossible
+    pub fn new(entries: Vec<FqiEntry>) -> Result<Self, Error>{
+        let mut name_to_index = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if (entry.line_bases == 0 && entry.length > 0) || entry.line_width < entry.line_bases {
+                return Err(Error::Index(format!("Invalid line length or width for read {}", entry.name)));
+            }
+            if name_to_index.insert(entry.name.clone(), i).is_some() {
+                return Err(Error::Index(format!("Duplicate read name {}", entry.name)));
+            }
+        }
+        Ok(Self{entries, name_to_index})
+    }
+
+    // Builds the index by reading through uncompressed FASTQ data
+    pub fn build<R: BufRead>(mut input: R) -> Result<Self, Error>{
+        let mut entries = Vec::<FqiEntry>::new();
+        let mut line = Vec::<u8>::new();
+        let mut offset = 0_u64; // Byte offset of the start of the current line
+        let mut state = BuildState::Header;
+        let different_lengths = |entry: &FqiEntry| Error::Index(format!("Different line lengths in read {}", entry.name));
+        loop {
+            line.clear();
+            let width = input.read_until(b'\n', &mut line)? as u64;
+            if width == 0 {
+                break;
+            }
+            let mut content = line.as_slice();
+            while let [rest @ .., b'\n' | b'\r'] = content {
+                content = rest;
+            }
+            let bases = content.len() as u64;
+
+            state = match state{
+                BuildState::Header => {
+                    let header = match content.strip_prefix(b"@"){
+                        Some(header) => header,
+                        None if content.is_empty() => { offset += width; continue } // Blank line between records
+                        None => return Err(Error::Index(format!("FASTQ header line does not start with @ at byte {}", offset))),
+                    };
+                    let name = header.split(|c| c.is_ascii_whitespace()).next().unwrap_or(header);
+                    entries.push(FqiEntry{name: String::from_utf8_lossy(name).into_owned(), length: 0, offset: offset + width, line_bases: 0, line_width: 0, qual_offset: 0});
+                    BuildState::Sequence{saw_short_line: false}
+                },
+                BuildState::Sequence{saw_short_line} => {
+                    let entry = entries.last_mut().unwrap(); // Pushed in the Header state
+                    if content.starts_with(b"+") {
+                        entry.qual_offset = offset + width;
+                        BuildState::Quality{remaining: entry.length}
+                    } else {
+                        let saw_short_line = if entry.length == 0 && !saw_short_line {
+                            // First line of the sequence
+                            entry.line_bases = bases;
+                            entry.line_width = width;
+                            bases == 0
+                        } else if saw_short_line || bases > entry.line_bases {
+                            return Err(different_lengths(entry));
+                        } else {
+                            bases != entry.line_bases || width != entry.line_width
+                        };
+                        entry.length += bases;
+                        BuildState::Sequence{saw_short_line}
+                    }
+                },
+                BuildState::Quality{remaining} => {
+                    let entry = entries.last().unwrap(); // Pushed in the Header state
+                    if bases > remaining {
+                        return Err(Error::Index(format!("More quality values than bases in read {}", entry.name)));
+                    }
+                    let last_line = bases == remaining;
+                    // The quality values must have the same line layout as the sequence
+                    if (!last_line && (bases != entry.line_bases || width != entry.line_width)) || (last_line && bases > entry.line_bases) {
+                        return Err(different_lengths(entry));
+                    }
+                    if last_line { BuildState::Header } else { BuildState::Quality{remaining: remaining - bases} }
+                },
+            };
+            offset += width;
+        }
+
+        match state{
+            BuildState::Header => Self::new(entries),
+            _ => Err(Error::Index("FASTQ file ended in the middle of a record".to_string())),
+        }
+    }
+
+    // Builds the index for a FASTQ file, which can be uncompressed or BGZF-compressed
+    pub fn build_from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Self::build(open_indexable_stream(filepath)?)
+    }
+
+    // Reads an index in the samtools fqidx .fai format
+    pub fn read<R: BufRead>(input: R) -> Result<Self, Error>{
+        let mut entries = Vec::new();
+        for (line_index, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let number = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+            match (number(1), number(2), number(3), number(4), number(5)){
+                (Some(length), Some(offset), Some(line_bases), Some(line_width), Some(qual_offset)) if fields.len() == 6 => {
+                    entries.push(FqiEntry{name: fields[0].to_string(), length, offset, line_bases, line_width, qual_offset});
+                },
+                _ => return Err(Error::Index(format!("Invalid fqidx .fai line {}: {}", line_index + 1, line))),
+            }
+        }
+        Self::new(entries)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Self::read(BufReader::new(File::open(filepath)?))
+    }
+
+    // Writes the index in the samtools fqidx .fai format
+    pub fn write<W: Write>(&self, mut output: W) -> Result<(), Error>{
+        for e in self.entries.iter() {
+            writeln!(output, "{}\t{}\t{}\t{}\t{}\t{}", e.name, e.length, e.offset, e.line_bases, e.line_width, e.qual_offset)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, filepath: &P) -> Result<(), Error>{
+        self.write(std::io::BufWriter::new(File::create(filepath)?))
+    }
+
+    pub fn entries(&self) -> &[FqiEntry]{
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FqiEntry>{
+        self.name_to_index.get(name).map(|&i| &self.entries[i])
+    }
+}
+
+// Looks up reads by name in an indexed FASTQ file. The file can be uncompressed, or compressed
+// with bgzip, in which case the .gzi index is used to find the blocks. The records are
+// parsed with StaticFastXReader and returned as they are in the file, without case conversion.
+pub struct IndexedFastqReader<R: Read + Seek = File>{
+    input: RangeReader<R>,
+    index: FastqIndex,
+    raw_buf: Vec<u8>, // The bytes of a record as they are in the file
+}
+
+impl IndexedFastqReader<File>{
+    // Opens a FASTQ file with its index at filepath.fai, or filepath.gzi as well for BGZF-compressed files.
+    // An index that does not exist is built by reading the file, but it is not written to disk.
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        let fai_path = with_added_extension(filepath.as_ref(), "fai");
+        let index = if fai_path.exists() { FastqIndex::from_file(&fai_path)? } else { FastqIndex::build_from_file(filepath)? };
+
+        Ok(Self{input: open_range_reader(filepath)?, index, raw_buf: Vec::new()})
+    }
+}
+
+impl<R: Read + Seek> IndexedFastqReader<R>{
+
+    // Reader for uncompressed FASTQ
+    pub fn new(input: R, index: FastqIndex) -> Self{
+        Self{input: RangeReader::Plain(input), index, raw_buf: Vec::new()}
+    }
+
+    // Reader for BGZF-compressed FASTQ
+    #[cfg(feature = "gzip")]
+    pub fn new_bgzf(input: R, index: FastqIndex, gzi: GziIndex) -> Self{
+        Self{input: RangeReader::Bgzf(BgzfRangeReader::new(input, gzi)), index, raw_buf: Vec::new()}
+    }
+
+    pub fn index(&self) -> &FastqIndex{
+        &self.index
+    }
+
+    // Returns the read with the given name. The header of the record is the name without the comment.
+    pub fn fetch(&mut self, name: &str) -> Result<OwnedRecord, Error>{
+        let entry = self.index.get(name).ok_or_else(|| Error::Index(format!("Read {} not found in the index", name)))?;
+        let (seq_offset, qual_end, length, multiline) = (entry.offset, entry.qual_end(), entry.length, entry.line_bases < entry.length);
+
+        // The index points to the sequence, so the header line is made up from the name
+        self.raw_buf.clear();
+        self.raw_buf.push(b'@');
+        self.raw_buf.extend_from_slice(name.as_bytes());
+        self.raw_buf.push(b'\n');
+        self.input.read_range(seq_offset, qual_end, &mut self.raw_buf)?;
+        self.raw_buf.push(b'\n');
+
+        let mut reader = ReaderBuilder::new().filetype(FileType::FASTQ).case_policy(CasePolicy::Keep)
+            .multiline_fastq(multiline).build_static(self.raw_buf.as_slice())?;
+        match reader.read_next()?{
+            Some(rec) if rec.seq.len() as u64 == length => Ok(rec.to_owned()),
+            _ => Err(Error::Index(format!("The index does not match the file at read {}", name))),
+        }
+    }
+}
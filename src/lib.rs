@@ -3,11 +3,10 @@
 //! 
 //! # Libary design
 //! In bioinformatics, sequences are usually stored in files in FASTA or FASTQ format,
-//! which are often compressed with gzip. This makes a total of four formats: FASTA with
-//! and without gzip, and FASTQ with and without gzip. The purpose of the crate is to provide
-//! a parser that can automatically detect the format of the file and parse it without
+//! which are often compressed with gzip, zstd, bzip2 or xz. The purpose of the crate is to provide
+//! a parser that can automatically detect the format and the compression of the file and parse it without
 //! the user having to know beforehand which format is being used. **The file format is detected
-//! from the first two bytes of the file, and does not depend on the file extension**.
+//! from the first bytes of the file, and does not depend on the file extension**.
 //! 
 //! We use dynamic dispatch to hide the details of the file format from the user. This introduces
 //! an overhead of one dynamic dispatch per sequence, which is likely negligible unless the sequences
@@ -22,8 +21,20 @@
 //! Since the readers stream over the data, we can not implement the Rust Iterator trait. The lifetime constraints
 //! on Rust Iterators require that all elements are valid until the end of the iteration. To support iterators,
 //! we provide the [seq_db::SeqDB] struct that concatenates all sequences, headers and quality values in memory and provides 
-//! an iterator over them.
-//! 
+//! an iterator over them. For one pass over a stream, [reader::SeqStream] has `for_each` and `try_for_each`, which call
+//! a closure on each record, and `into_owned_iter`, which copies each record into an [record::OwnedRecord].
+//! The trait [adapters::SeqStreamExt] adds lazy adapters like `filter`, `take` and `map_mut`, which are streams themselves.
+//! The [sample] module takes reproducible random subsamples of streams with a seed.
+//!
+//! Each compression format is behind a cargo feature of the same name: `gzip`, `zstd`, `bzip2` and `xz`.
+//! All of them are enabled by default, together with `bgzf-parallel` for decompressing BGZF files on
+//! multiple threads. Opening a file whose compression is not enabled returns [Error::CompressionDisabled].
+//! The default `mmap` feature adds [mmap::MmapFastXReader] for reading uncompressed files without copying.
+//! The `async` feature adds readers and writers for tokio streams.
+//!
+//! All fallible functions return the error type [Error], which tells apart I/O errors, parse errors,
+//! decompression errors and unsupported formats.
+//!
 //! # Examples
 //! 
 //! ## Streaming all sequences in a file and printing them to the standard output.
@@ -31,9 +42,9 @@
 //! ```
 //! use jseqio::reader::*;
 //! fn main() -> Result<(), Box<dyn std::error::Error>>{
-//!     // Reading from a FASTQ file. Also works for FASTA,
-//!     // and seamlessly with/without gzip compression.
-//!     let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq.gz")?;
+//!     // Reading from a FASTQ file. Also works for FASTA, and seamlessly
+//!     // for compressed files when the feature of the compression is enabled.
+//!     let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq")?;
 //!     while let Some(rec) = reader.read_next().unwrap() {
 //!         // Headers do not include the leading '>' in FASTA or '@' in FASTQ.
 //!         eprintln!("Header: {}", std::str::from_utf8(rec.head)?);
@@ -64,38 +75,118 @@
 use std::path::Path;
 
 pub mod reader;
+mod parser;
 pub mod writer;
 pub mod record;
 pub mod seq_db;
+pub mod error;
+pub mod paired;
+pub mod multi;
+pub mod batch;
+pub mod pipeline;
+pub mod adapters;
+pub mod sample;
+#[cfg(feature = "gzip")]
+pub mod bgzf;
+pub mod faidx;
+pub mod fqidx;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "async")]
+pub mod async_reader;
+#[cfg(feature = "async")]
+pub mod async_writer;
+
+pub use error::Error;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum FileType{
     FASTA,
     FASTQ,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CompressionType{
     Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
     None,
-} // This could be just a bool, but in the future we may want to support other compression types as well
+}
+
+impl CompressionType{
+    // The cargo feature of this crate that is needed to read and write this compression type
+    pub fn feature_name(&self) -> &'static str{
+        match self{
+            CompressionType::Gzip => "gzip",
+            CompressionType::Zstd => "zstd",
+            CompressionType::Bzip2 => "bzip2",
+            CompressionType::Xz => "xz",
+            CompressionType::None => "",
+        }
+    }
+}
+
+const FASTA_EXTENSIONS: [&str; 6] = [".fasta", ".fna", ".ffn", ".faa", ".frn", ".fa"];
+const FASTQ_EXTENSIONS: [&str; 2] = [".fastq", ".fq"];
+
+// Figures out the file type and compression type from the file extension, for example
+// "reads.fq.gz" or "genome.FASTA". The extension is matched case-insensitively.
+// Returns Error::Format if the extension is not recognized or the compression type is not supported.
+pub fn detect_format<P: AsRef<Path>>(filepath: P) -> Result<(FileType, CompressionType), Error>{
+    let filename = match filepath.as_ref().file_name(){
+        Some(name) => name.to_string_lossy().to_ascii_lowercase(),
+        None => return Err(Error::Format(format!("Not a file path: {}", filepath.as_ref().display()))),
+    };
+
+    let (filename, compression_type) = if let Some(stem) = filename.strip_suffix(".gz").or(filename.strip_suffix(".bgz")){
+        (stem, CompressionType::Gzip)
+    } else if let Some(stem) = filename.strip_suffix(".zst").or(filename.strip_suffix(".zstd")){
+        (stem, CompressionType::Zstd)
+    } else if let Some(stem) = filename.strip_suffix(".bz2"){
+        (stem, CompressionType::Bzip2)
+    } else if let Some(stem) = filename.strip_suffix(".xz"){
+        (stem, CompressionType::Xz)
+    } else {
+        (filename.as_str(), CompressionType::None)
+    };
+
+    if FASTA_EXTENSIONS.iter().any(|&suffix| filename.ends_with(suffix)){
+        Ok((FileType::FASTA, compression_type))
+    } else if FASTQ_EXTENSIONS.iter().any(|&suffix| filename.ends_with(suffix)){
+        Ok((FileType::FASTQ, compression_type))
+    } else{
+        Err(Error::Format(format!("Unknown file extension: {}", filepath.as_ref().display())))
+    }
+}
+
+// Figures out the file type and compression type from the contents of an existing file
+// the same way as reader::DynamicFastXReader does, regardless of the file extension.
+// Only the start of the file is read and decompressed.
+pub fn sniff_format<P: AsRef<Path>>(filepath: P) -> Result<(FileType, CompressionType), Error>{
+    let mut input = std::io::BufReader::new(ex::fs::File::open(&filepath)?);
+    let prefix = reader::read_prefix(&mut input)?;
+    let compression_type = reader::detect_compression_from_bytes(&prefix)?;
+    let decompressed = reader::read_decompressed_prefix(std::io::Read::chain(std::io::Cursor::new(prefix), input), compression_type)?;
+    match reader::detect_filetype(&decompressed){
+        Ok(filetype) => Ok((filetype, compression_type)),
+        Err(Error::Parse(mut e)) => {
+            e.filename = Some(filepath.as_ref().to_string_lossy().into_owned());
+            Err(Error::Parse(e))
+        },
+        Err(e) => Err(e),
+    }
+}
 
 // Returns (file type, is_gzipped)
+// Panics if the file extension is not recognized or the file is compressed with something other than gzip.
+// Use detect_format for a version that returns an error instead.
 pub fn figure_out_file_format<P: AsRef<Path>>(filepath: P) -> (FileType, bool){
-    let filename = filepath.as_ref().as_os_str().to_str().unwrap();
-
-    let is_gzipped = filename.ends_with(".gz");
-    let filename = if is_gzipped{
-        &filename[0 .. filename.len()-3] // Drop the .gz suffix
-    } else {filename};
-    let fasta_extensions = vec![".fasta", ".fna", ".ffn", ".faa", ".frn", ".fa"];
-    let fastq_extensions = vec![".fastq", ".fq"];
-    if fasta_extensions.iter().any(|&suffix| filename.ends_with(suffix)){
-        (FileType::FASTA, is_gzipped)
-    } else if fastq_extensions.iter().any(|&suffix| filename.ends_with(suffix)){
-        (FileType::FASTQ, is_gzipped)
-    } else{
-        panic!("Unkown file extension: {}", filename);
+    match detect_format(&filepath){
+        Ok((filetype, CompressionType::Gzip)) => (filetype, true),
+        Ok((filetype, CompressionType::None)) => (filetype, false),
+        Ok((_, compression_type)) => panic!("Compression type {:?} of {} can not be returned as a bool. Use detect_format.", compression_type, filepath.as_ref().display()),
+        Err(e) => panic!("{}", e),
     }
 }
 
@@ -117,6 +208,7 @@ pub fn reverse_complement(seq: &[u8]) -> Vec<u8>{
     seq.iter().rev().map(|&c| complement(c)).collect()
 }
 
+#[allow(clippy::needless_range_loop)]
 pub fn reverse_complement_in_place(seq: &mut [u8]){
     for i in 0..seq.len(){
         seq[i] = complement(seq[i]);
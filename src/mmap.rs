@@ -0,0 +1,319 @@
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::{Error, FileType};
+use crate::batch::RecordBatch;
+use crate::parser::{check_header, check_plus_line, check_quality_length, is_fasta_header, is_plus_line,
+    EMPTY_FASTA_SEQUENCE, MISSING_PLUS_LINE, MISSING_QUALITY_LINE, MISSING_SEQUENCE_LINE};
+use crate::reader::{CasePolicy, InputPosition, ParseError, ReaderBuilder, SeqStream};
+use crate::record::RefRecord;
+use crate::seq_db::SeqDB;
+
+// Reads an uncompressed FASTA or FASTQ file through a memory mapping. The records point directly
+// into the mapped file, so nothing is copied unless a sequence or quality string spans multiple
+// lines or the case policy changes some of the letters. In those cases the field is copied into
+// a buffer of the reader. The records are checked with the same rules and error messages as in
+// the parser of StaticFastXReader.
+//
+// The file must not be modified or truncated while it is mapped. Reading a file that another process
+// changes at the same time is undefined behavior, and typically crashes the program with SIGBUS.
+pub struct MmapFastXReader{
+    data: Mmap,
+    filetype: FileType,
+    filename: Option<String>, // Only used for error messages
+    position: InputPosition, // The byte offset is where the next line starts in the mapped file
+    fasta_next_header: Option<Range<usize>>, // The FASTA header line read at the end of the previous record
+    seq_buf: Vec<u8>,
+    qual_buf: Vec<u8>,
+    saw_crlf: bool, // Whether any line so far ended in "\r\n"
+    case_policy: CasePolicy,
+    multiline_fastq: bool, // Whether FASTQ sequences and qualities may span multiple lines
+}
+
+// Where a sequence or quality string of the current record is
+#[derive(Clone, Debug)]
+enum Field{
+    Mapped(Range<usize>), // On a single line in the mapped file
+    Buffer, // Copied into the buffer of the field
+}
+
+// The parts of the current record. The header is always on one line in the mapped file.
+struct RecordFields{
+    head: Range<usize>,
+    seq: Field,
+    qual: Option<Field>,
+}
+
+// Adds a line to a field that may span multiple lines. A field stays in the mapped file
+// as long as it has only one line, and is copied into buf when the second line comes.
+fn push_line(data: &[u8], field: Option<Field>, buf: &mut Vec<u8>, line: Range<usize>) -> Field{
+    match field{
+        None => Field::Mapped(line),
+        Some(Field::Mapped(first)) => {
+            buf.clear();
+            buf.extend_from_slice(&data[first]);
+            buf.extend_from_slice(&data[line]);
+            Field::Buffer
+        },
+        Some(Field::Buffer) => {
+            buf.extend_from_slice(&data[line]);
+            Field::Buffer
+        },
+    }
+}
+
+fn field_bytes<'a>(data: &'a [u8], field: &Field, buf: &'a [u8]) -> &'a [u8]{
+    match field{
+        Field::Mapped(range) => &data[range.clone()],
+        Field::Buffer => buf,
+    }
+}
+
+impl MmapFastXReader{
+
+    // Maps the file and detects the format from the first byte. Returns an error if the file is compressed.
+    // Use ReaderBuilder::mmap_file to set the options before reading.
+    pub fn from_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        ReaderBuilder::new().mmap_file(filepath)
+    }
+
+    pub(crate) fn new(data: Mmap, filetype: FileType, filename: Option<String>) -> Self{
+        MmapFastXReader{data,
+                    filetype,
+                    filename,
+                    position: InputPosition::default(),
+                    fasta_next_header: None,
+                    seq_buf: Vec::new(),
+                    qual_buf: Vec::new(),
+                    saw_crlf: false,
+                    case_policy: CasePolicy::default(),
+                    multiline_fastq: false,}
+    }
+
+    fn build_parse_error(&self, message: &str) -> Error{
+        Error::Parse(
+            ParseError{
+                message: message.to_owned(),
+                filename: self.filename.clone(),
+                filetype: Some(self.filetype),
+                line: self.position.line,
+                byte_offset: self.position.line_start,
+                record_index: self.position.record_index,
+            }
+        )
+    }
+
+    // Returns the range of the next line including the line end, or None at the end of the file
+    fn next_line(&mut self) -> Option<Range<usize>>{
+        let start = self.position.byte_offset as usize;
+        let rest = &self.data[start..];
+        if rest.is_empty() {
+            return None;
+        }
+        let len = rest.iter().position(|&c| c == b'\n').map_or(rest.len(), |i| i + 1);
+        self.position.advance(len);
+        Some(start..start + len)
+    }
+
+    // Removes the trailing "\n" or "\r\n" from a line, if there is one
+    fn trim_line_end(&mut self, mut line: Range<usize>) -> Range<usize>{
+        if line.end > line.start && self.data[line.end - 1] == b'\n' {
+            line.end -= 1;
+            if line.end > line.start && self.data[line.end - 1] == b'\r' {
+                line.end -= 1;
+                self.saw_crlf = true;
+            }
+        }
+        line
+    }
+
+    // The mapped file is read-only, so a sequence is copied into the buffer if the case policy changes it
+    fn apply_case_policy(&mut self, seq: Field) -> Field{
+        if let Field::Mapped(range) = &seq {
+            if !self.case_policy.changes(&self.data[range.clone()]) {
+                return seq;
+            }
+            self.seq_buf.clear();
+            self.seq_buf.extend_from_slice(&self.data[range.clone()]);
+        }
+        self.case_policy.apply(&mut self.seq_buf);
+        Field::Buffer
+    }
+
+    fn read_fasta_record(&mut self) -> Result<Option<RecordFields>, Error>{
+        // Read header line, or take the one found at the end of the previous record
+        let head = match self.fasta_next_header.take().or_else(|| self.next_line()){
+            Some(line) => line,
+            None => return Ok(None), // End of file
+        };
+        check_header(FileType::FASTA, &self.data[head.clone()]).map_err(|message| self.build_parse_error(&message))?;
+
+        // Read sequence lines until the next header
+        let mut seq = None;
+        while let Some(line) = self.next_line(){
+            if is_fasta_header(&self.data[line.clone()]){
+                self.fasta_next_header = Some(line);
+                break;
+            }
+            let line = self.trim_line_end(line);
+            seq = Some(push_line(&self.data, seq, &mut self.seq_buf, line));
+        }
+        let seq = seq.unwrap_or(Field::Mapped(0..0));
+        if self.fasta_next_header.is_none() && field_bytes(&self.data, &seq, &self.seq_buf).is_empty(){
+            // Stream ends with an empty sequence
+            return Err(self.build_parse_error(EMPTY_FASTA_SEQUENCE));
+        }
+        let seq = self.apply_case_policy(seq);
+
+        let head = self.trim_line_end(head);
+        self.position.record_index += 1;
+        Ok(Some(RecordFields{head: head.start + 1..head.end, seq, qual: None})) // Remove '>'
+    }
+
+    fn read_fastq_record(&mut self) -> Result<Option<RecordFields>, Error>{
+        // Read header line
+        let head = match self.next_line(){
+            Some(line) => line,
+            None => return Ok(None), // End of file
+        };
+        check_header(FileType::FASTQ, &self.data[head.clone()]).map_err(|message| self.build_parse_error(&message))?;
+        let head = self.trim_line_end(head);
+
+        let (seq, qual) = if self.multiline_fastq {
+            self.read_multiline_fastq_body()?
+        } else {
+            self.read_four_line_fastq_body()?
+        };
+        let seq = self.apply_case_policy(seq);
+
+        self.position.record_index += 1;
+        Ok(Some(RecordFields{head: head.start + 1..head.end, seq, qual: Some(qual)})) // Remove '@'
+    }
+
+    // Reads the sequence, plus and quality lines of a record where each of them is on a single line
+    fn read_four_line_fastq_body(&mut self) -> Result<(Field, Field), Error>{
+        let seq = self.next_line().ok_or_else(|| self.build_parse_error(MISSING_SEQUENCE_LINE))?;
+        let seq = self.trim_line_end(seq);
+
+        let plus = self.next_line().ok_or_else(|| self.build_parse_error(MISSING_PLUS_LINE))?;
+        check_plus_line(&self.data[plus]).map_err(|message| self.build_parse_error(&message))?;
+
+        let qual = self.next_line().ok_or_else(|| self.build_parse_error(MISSING_QUALITY_LINE))?;
+        let qual = self.trim_line_end(qual);
+        check_quality_length(qual.len(), seq.len(), false).map_err(|message| self.build_parse_error(&message))?;
+        Ok((Field::Mapped(seq), Field::Mapped(qual)))
+    }
+
+    // Reads sequence lines until the + line, and then quality lines until there are
+    // as many quality values as there are bases.
+    fn read_multiline_fastq_body(&mut self) -> Result<(Field, Field), Error>{
+        let mut seq = None;
+        loop{
+            let line = self.next_line().ok_or_else(|| self.build_parse_error(MISSING_PLUS_LINE))?;
+            if is_plus_line(&self.data[line.clone()]) {
+                break; // Found the + line
+            }
+            let line = self.trim_line_end(line);
+            seq = Some(push_line(&self.data, seq, &mut self.seq_buf, line));
+        }
+        let seq = seq.unwrap_or(Field::Mapped(0..0));
+        let seq_len = field_bytes(&self.data, &seq, &self.seq_buf).len();
+
+        // There is always at least one quality line, even if the sequence is empty
+        let mut qual = None;
+        let qual_len = loop{
+            let line = self.next_line().ok_or_else(|| self.build_parse_error(MISSING_QUALITY_LINE))?;
+            let line = self.trim_line_end(line);
+            let field = push_line(&self.data, qual, &mut self.qual_buf, line);
+            let qual_len = field_bytes(&self.data, &field, &self.qual_buf).len();
+            qual = Some(field);
+            if qual_len >= seq_len {
+                break qual_len;
+            }
+        };
+
+        check_quality_length(qual_len, seq_len, true).map_err(|message| self.build_parse_error(&message))?;
+        Ok((seq, qual.unwrap())) // The loop ran at least once
+    }
+
+    // Read one record from the file.
+    // This is not named just next() because it's not a Rust iterator because it streams the input.
+    pub fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        let fields = match self.filetype{
+            FileType::FASTA => self.read_fasta_record()?,
+            FileType::FASTQ => self.read_fastq_record()?,
+        };
+        Ok(fields.map(|fields| RefRecord{
+            head: &self.data[fields.head],
+            seq: field_bytes(&self.data, &fields.seq, &self.seq_buf),
+            qual: fields.qual.map(|qual| field_bytes(&self.data, &qual, &self.qual_buf)),
+        }))
+    }
+
+    // Clears the batch and fills it with the next records, until the batch has max_records records
+    // or at least max_bytes bytes of headers, sequences and quality values. Returns the number
+    // of records read, which is 0 only at the end of the file.
+    pub fn read_batch(&mut self, batch: &mut RecordBatch, max_records: usize, max_bytes: usize) -> Result<usize, Error>{
+        crate::batch::fill_batch(self, batch, max_records, max_bytes)
+    }
+
+    pub fn into_db(mut self) -> Result<SeqDB, Error>{
+        let mut db = SeqDB::new();
+        while let Some(rec) = self.read_next()?{
+            db.push_record(rec);
+        }
+        db.shrink_to_fit();
+        Ok(db)
+    }
+
+    // Allows FASTQ records where the sequence and quality values are wrapped over multiple lines.
+    // This is off by default because the four-line format is faster to parse.
+    pub fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.multiline_fastq = multiline_fastq;
+    }
+
+    // Sets the case conversion applied to the sequences. The default is CasePolicy::Upper.
+    // Sequences that need to be converted are copied, so CasePolicy::Keep avoids the copies.
+    pub fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.case_policy = case_policy;
+    }
+
+    pub fn filetype(&self) -> FileType{
+        self.filetype
+    }
+
+    // Whether any of the lines read so far ended in "\r\n" (Windows line endings).
+    // The "\r" characters are always stripped from the records.
+    pub fn saw_crlf(&self) -> bool{
+        self.saw_crlf
+    }
+
+    // Returns true if the whole file has been read. If this returns false,
+    // the next call to read_next returns either a record or an error.
+    pub fn is_at_end(&self) -> bool{
+        self.fasta_next_header.is_none() && self.position.byte_offset as usize == self.data.len()
+    }
+
+    // 1-based number of the line read last, or zero if nothing has been read yet.
+    pub fn line_number(&self) -> u64{
+        self.position.line
+    }
+
+    // Number of bytes of the file read so far.
+    pub fn byte_offset(&self) -> u64{
+        self.position.byte_offset
+    }
+
+    // Number of records read so far, which is also the 0-based index of the next record.
+    pub fn record_index(&self) -> u64{
+        self.position.record_index
+    }
+}
+
+impl SeqStream for MmapFastXReader {
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
+        MmapFastXReader::read_next(self)
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Error, FileType};
+use crate::reader::{DynamicFastXReader, ReaderBuilder, SeqStream};
+use crate::record::RefRecord;
+
+// What to do if some of the input files are FASTA and some are FASTQ
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum MixedFormatPolicy{
+    #[default]
+    Reject, // Return Error::Format when reaching the first file with a different format
+    Allow, // Records from FASTA files have no quality values, and records from FASTQ files do
+}
+
+// Reads many files one after the other as if they were one stream, for example
+// the lane files L001..L004 of a sample. A path "-" means the standard input.
+// Each file is opened only when the previous one has been read to the end, with
+// the same gzip and format detection as DynamicFastXReader.
+pub struct MultiFastXReader{
+    paths: Vec<PathBuf>,
+    builder: ReaderBuilder,
+    mixed_format_policy: MixedFormatPolicy,
+    current: Option<DynamicFastXReader>,
+    file_index: usize, // Index of the current file in paths
+    records_in_file: u64, // Number of records read from the current file
+    filetype: Option<FileType>, // File type of the first non-empty file
+}
+
+impl MultiFastXReader{
+
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Self{
+        Self{
+            paths: paths.iter().map(|p| p.as_ref().to_path_buf()).collect(),
+            builder: ReaderBuilder::new(),
+            mixed_format_policy: MixedFormatPolicy::default(),
+            current: None,
+            file_index: 0,
+            records_in_file: 0,
+            filetype: None,
+        }
+    }
+
+    // Options for opening each of the files. The filename option of the builder is ignored.
+    pub fn set_reader_builder(&mut self, builder: ReaderBuilder){
+        self.builder = builder;
+    }
+
+    pub fn set_mixed_format_policy(&mut self, mixed_format_policy: MixedFormatPolicy){
+        self.mixed_format_policy = mixed_format_policy;
+    }
+
+    // 0-based index of the file that the record returned last came from.
+    pub fn source_file_index(&self) -> usize{
+        self.file_index
+    }
+
+    // Path of the file that the record returned last came from, or None if all files have been read.
+    pub fn source_path(&self) -> Option<&Path>{
+        self.paths.get(self.file_index).map(|p| p.as_path())
+    }
+
+    // 0-based index of the record returned last, within the file that it came from.
+    pub fn source_record_index(&self) -> u64{
+        self.records_in_file.saturating_sub(1)
+    }
+
+    // The file type of the first non-empty file, or None if no records have been read yet.
+    pub fn filetype(&self) -> Option<FileType>{
+        self.filetype
+    }
+
+    // Opens the file at self.file_index. Returns false if there are no more files.
+    fn open_current_file(&mut self) -> Result<bool, Error>{
+        let path = match self.paths.get(self.file_index){
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let reader = if path.as_os_str() == "-" {
+            self.builder.clone().filename("<stdin>").from_stdin()?
+        } else {
+            self.builder.clone().filename(path.to_string_lossy()).from_file(path)?
+        };
+        self.current = Some(reader);
+        Ok(true)
+    }
+
+    fn check_filetype(&mut self, filetype: FileType) -> Result<(), Error>{
+        match self.filetype{
+            None => self.filetype = Some(filetype),
+            Some(first) if first != filetype && self.mixed_format_policy == MixedFormatPolicy::Reject => {
+                return Err(Error::Format(format!("Mixed FASTA and FASTQ inputs: {} is {:?} but the previous inputs are {:?}",
+                    self.paths[self.file_index].display(), filetype, first)));
+            },
+            Some(_) => (),
+        }
+        Ok(())
+    }
+
+    pub fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        // Find the next file that has records left
+        loop{
+            match self.current.as_mut(){
+                Some(reader) => {
+                    if !reader.is_at_end()? {
+                        let filetype = reader.filetype();
+                        self.check_filetype(filetype)?;
+                        break;
+                    }
+                    // Move on to the next file
+                    self.current = None;
+                    self.file_index += 1;
+                },
+                None => {
+                    if !self.open_current_file()? {
+                        return Ok(None); // All files have been read
+                    }
+                    self.records_in_file = 0;
+                },
+            }
+        }
+
+        let records_in_file = &mut self.records_in_file;
+        match self.current.as_mut(){
+            Some(reader) => {
+                let rec = reader.read_next()?;
+                if rec.is_some() {
+                    *records_in_file += 1; // Only records that were read successfully are counted
+                }
+                Ok(rec)
+            },
+            None => Ok(None), // Not reachable because the loop above only breaks with an open reader
+        }
+    }
+}
+
+impl SeqStream for MultiFastXReader{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
+        MultiFastXReader::read_next(self)
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use crate::Error;
+use crate::reader::{DynamicFastXReader, SeqStream};
+use crate::record::{OwnedRecord, Record, RefRecord};
+use crate::writer::{DynamicFastXWriter, SeqRecordWriter};
+
+// Returns the part of a read header that must be the same for both mates of a pair:
+// the first whitespace-separated token (dropping Casava comments like "1:N:0:ATCACG"),
+// without a trailing "/1" or "/2".
+pub fn mate_name(head: &[u8]) -> &[u8]{
+    let name = head.split(|c| *c == b' ' || *c == b'\t').next().unwrap_or(head);
+    match name{
+        [prefix @ .., b'/', b'1' | b'2'] => prefix,
+        _ => name,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairErrorKind{
+    NameMismatch{name1: Vec<u8>, name2: Vec<u8>}, // The mate names after stripping suffixes and comments
+    FirstEndedEarly, // The first input ended before the second
+    SecondEndedEarly, // The second input ended before the first
+    MissingMate, // An interleaved input has an odd number of records
+}
+
+// The two mates of a pair do not match
+#[derive(Debug)]
+pub struct PairError{
+    pub kind: PairErrorKind,
+    pub record_index: u64, // 0-based index of the pair where the error happened
+}
+
+impl std::error::Error for PairError{}
+
+impl std::fmt::Display for PairError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind{
+            PairErrorKind::NameMismatch{name1, name2} => write!(f, "Mate names differ at pair {}: {} vs {}",
+                self.record_index, String::from_utf8_lossy(name1), String::from_utf8_lossy(name2)),
+            PairErrorKind::FirstEndedEarly => write!(f, "First input ended before the second at pair {}", self.record_index),
+            PairErrorKind::SecondEndedEarly => write!(f, "Second input ended before the first at pair {}", self.record_index),
+            PairErrorKind::MissingMate => write!(f, "Interleaved input ended in the middle of pair {}", self.record_index),
+        }
+    }
+}
+
+// Checks that two records are mates of each other. Used by the paired readers.
+pub(crate) fn check_mates(rec1: &RefRecord, rec2: &RefRecord, record_index: u64) -> Result<(), Error>{
+    let name1 = mate_name(rec1.head);
+    let name2 = mate_name(rec2.head);
+    if name1 != name2 {
+        return Err(Error::Pair(PairError{
+            kind: PairErrorKind::NameMismatch{name1: name1.to_vec(), name2: name2.to_vec()},
+            record_index,
+        }));
+    }
+    Ok(())
+}
+
+// Reads paired-end data from two streams in lockstep, for example _R1.fq.gz and _R2.fq.gz.
+// Returns an error if one stream ends before the other, or if the mate names do not match.
+pub struct PairedFastXReader<S: SeqStream = DynamicFastXReader>{
+    reader1: S,
+    reader2: S,
+    record_index: u64,
+    check_names: bool,
+}
+
+impl PairedFastXReader<DynamicFastXReader>{
+    pub fn from_files<P1: AsRef<Path>, P2: AsRef<Path>>(filepath1: &P1, filepath2: &P2) -> Result<Self, Error>{
+        let reader1 = DynamicFastXReader::from_file(filepath1)?;
+        let reader2 = DynamicFastXReader::from_file(filepath2)?;
+        Ok(Self::new(reader1, reader2))
+    }
+}
+
+impl<S: SeqStream> PairedFastXReader<S>{
+    pub fn new(reader1: S, reader2: S) -> Self{
+        Self{reader1, reader2, record_index: 0, check_names: true}
+    }
+
+    // Turn off the check that the mate names match. On by default.
+    pub fn set_check_names(&mut self, check_names: bool){
+        self.check_names = check_names;
+    }
+
+    // Number of pairs read so far
+    pub fn record_index(&self) -> u64{
+        self.record_index
+    }
+
+    // Returns the next pair (mate 1, mate 2), or None if both streams have ended.
+    pub fn read_next(&mut self) -> Result<Option<(RefRecord<'_>, RefRecord<'_>)>, Error>{
+        let rec1 = self.reader1.read_next()?;
+        let rec2 = self.reader2.read_next()?;
+        let kind = match (rec1, rec2){
+            (None, None) => return Ok(None),
+            (Some(rec1), Some(rec2)) => {
+                if self.check_names {
+                    check_mates(&rec1, &rec2, self.record_index)?;
+                }
+                self.record_index += 1;
+                return Ok(Some((rec1, rec2)));
+            },
+            (None, Some(_)) => PairErrorKind::FirstEndedEarly,
+            (Some(_), None) => PairErrorKind::SecondEndedEarly,
+        };
+        Err(Error::Pair(PairError{kind, record_index: self.record_index}))
+    }
+
+    pub fn into_inner(self) -> (S, S){
+        (self.reader1, self.reader2)
+    }
+}
+
+// Turns a stream where the mates of each pair are consecutive records (interleaved FASTQ)
+// into a stream of pairs. Returns an error if the mate names do not match or the stream
+// ends in the middle of a pair.
+pub struct InterleavedPairReader<S: SeqStream>{
+    inner: S,
+    mate1: OwnedRecord, // Copy of the first mate, because the inner stream reuses its buffers for the second
+    record_index: u64,
+    check_names: bool,
+}
+
+impl<S: SeqStream> InterleavedPairReader<S>{
+    pub fn new(inner: S) -> Self{
+        Self{
+            inner,
+            mate1: OwnedRecord{head: Vec::new(), seq: Vec::new(), qual: None},
+            record_index: 0,
+            check_names: true,
+        }
+    }
+
+    // Turn off the check that the mate names match. On by default.
+    pub fn set_check_names(&mut self, check_names: bool){
+        self.check_names = check_names;
+    }
+
+    // Number of pairs read so far
+    pub fn record_index(&self) -> u64{
+        self.record_index
+    }
+
+    // Returns the next pair (mate 1, mate 2), or None if the stream has ended.
+    pub fn read_next(&mut self) -> Result<Option<(RefRecord<'_>, RefRecord<'_>)>, Error>{
+        match self.inner.read_next()?{
+            None => return Ok(None),
+            Some(rec) => {
+                // Copy to self storage, reusing the allocated memory
+                self.mate1.head.clear();
+                self.mate1.head.extend_from_slice(rec.head);
+                self.mate1.seq.clear();
+                self.mate1.seq.extend_from_slice(rec.seq);
+                match (rec.qual, &mut self.mate1.qual){
+                    (Some(q), Some(buf)) => {buf.clear(); buf.extend_from_slice(q)},
+                    (Some(q), None) => self.mate1.qual = Some(q.to_vec()),
+                    (None, _) => self.mate1.qual = None,
+                }
+            }
+        }
+
+        let mate2 = match self.inner.read_next()?{
+            Some(rec) => rec,
+            None => return Err(Error::Pair(PairError{kind: PairErrorKind::MissingMate, record_index: self.record_index})),
+        };
+        let mate1 = self.mate1.as_ref_record();
+        if self.check_names {
+            check_mates(&mate1, &mate2, self.record_index)?;
+        }
+        self.record_index += 1;
+        Ok(Some((mate1, mate2)))
+    }
+
+    pub fn into_inner(self) -> S{
+        self.inner
+    }
+}
+
+// Writes the mates of each pair as consecutive records (interleaved FASTQ).
+pub struct InterleavedFastXWriter{
+    output: DynamicFastXWriter,
+}
+
+impl InterleavedFastXWriter{
+    pub fn new(output: DynamicFastXWriter) -> Self{
+        Self{output}
+    }
+
+    // The file type and compression are detected from the file extension.
+    pub fn new_to_file<P: AsRef<Path>>(filepath: &P) -> Result<Self, Error>{
+        Ok(Self::new(DynamicFastXWriter::new_to_file(filepath)?))
+    }
+
+    pub fn write_pair<R1: Record, R2: Record>(&mut self, mate1: &R1, mate2: &R2) -> Result<(), Error>{
+        self.output.write(mate1)?;
+        self.output.write(mate2)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error>{
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> DynamicFastXWriter{
+        self.output
+    }
+}
+
+// Splits an interleaved file into separate files for the first and second mates.
+// The output formats are detected from the file extensions. Returns the number of pairs.
+pub fn split_interleaved<P: AsRef<Path>, P1: AsRef<Path>, P2: AsRef<Path>>(input: &P, output1: &P1, output2: &P2) -> Result<u64, Error>{
+    let mut reader = InterleavedPairReader::new(DynamicFastXReader::from_file(input)?);
+    let mut writer1 = DynamicFastXWriter::new_to_file(output1)?;
+    let mut writer2 = DynamicFastXWriter::new_to_file(output2)?;
+    while let Some((mate1, mate2)) = reader.read_next()?{
+        writer1.write(&mate1)?;
+        writer2.write(&mate2)?;
+    }
+    writer1.flush()?;
+    writer2.flush()?;
+    Ok(reader.record_index())
+}
+
+// Merges separate files for the first and second mates into one interleaved file.
+// The output format is detected from the file extension. Returns the number of pairs.
+pub fn merge_paired<P1: AsRef<Path>, P2: AsRef<Path>, P: AsRef<Path>>(input1: &P1, input2: &P2, output: &P) -> Result<u64, Error>{
+    let mut reader = PairedFastXReader::from_files(input1, input2)?;
+    let mut writer = InterleavedFastXWriter::new_to_file(output)?;
+    while let Some((mate1, mate2)) = reader.read_next()?{
+        writer.write_pair(&mate1, &mate2)?;
+    }
+    writer.flush()?;
+    Ok(reader.record_index())
+}
@@ -0,0 +1,288 @@
+use crate::FileType;
+use crate::reader::{CasePolicy, InputPosition};
+use crate::record::MutRefRecord;
+
+// The FASTA and FASTQ parsing rules, shared by the readers over synchronous and asynchronous streams.
+// The parser does no I/O. For each record, the reader calls start_record with the file type and then feeds the
+// parser one line at a time: it appends the next line of the input to line_buf() with read_until
+// and passes the number of bytes read to push_line, until push_line returns Step::Record or Step::End.
+// The record is then available from record() until the next call to start_record.
+#[derive(Debug)]
+pub(crate) struct RecordParser{
+    seq_buf: Vec<u8>,
+    head_buf: Vec<u8>,
+    qual_buf: Vec<u8>,
+    plus_buf: Vec<u8>, // For the fastq plus-line
+    fasta_temp_buf: Vec<u8>, // Stores the fasta header read in the previous iteration
+    filetype: FileType, // Of the current record
+    state: State,
+    position: InputPosition, // For error messages
+    saw_crlf: bool, // Whether any line so far ended in "\r\n"
+    case_policy: CasePolicy,
+    multiline_fastq: bool, // Whether FASTQ sequences and qualities may span multiple lines
+}
+
+// Which line the parser expects next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State{
+    Header,
+    FastaSequence, // Any number of sequence lines, until the next header or the end of the input
+    FastqSequence, // In the four-line format, each of the sequence, + and quality is one line
+    FastqPlus,
+    FastqQuality,
+    FastqMultilineSequence, // Sequence lines until the + line
+    FastqMultilineQuality, // Quality lines until there are as many quality values as bases
+}
+
+// What the reader should do after push_line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Step{
+    NeedLine, // Read another line into line_buf()
+    Record, // A record is complete
+    End, // The input ended before the next record
+}
+
+// The checks of the FASTA and FASTQ rules and their error messages. They take lines without the line end,
+// except for the first byte checks, and are shared with the memory-mapped reader so that both give the same errors.
+pub(crate) const EMPTY_FASTA_SEQUENCE: &str = "Empty sequence in FASTA file";
+pub(crate) const MISSING_SEQUENCE_LINE: &str = "FASTQ sequence line missing.";
+pub(crate) const MISSING_PLUS_LINE: &str = "FASTQ + line missing.";
+pub(crate) const MISSING_QUALITY_LINE: &str = "FASTQ quality line missing.";
+
+// Checks that a header line starts with '>' for FASTA or '@' for FASTQ
+pub(crate) fn check_header(filetype: FileType, line: &[u8]) -> Result<(), String>{
+    match filetype{
+        FileType::FASTA if line.first() != Some(&b'>') => Err("FASTA header line does not start with >".to_owned()),
+        FileType::FASTQ if line.first() != Some(&b'@') => Err("FASTQ header line does not start with @".to_owned()),
+        _ => Ok(()),
+    }
+}
+
+// Whether a line in a FASTA sequence is the header of the next record
+pub(crate) fn is_fasta_header(line: &[u8]) -> bool{
+    line.first() == Some(&b'>')
+}
+
+// Whether a line is the + line of a FASTQ record
+pub(crate) fn is_plus_line(line: &[u8]) -> bool{
+    line.first() == Some(&b'+')
+}
+
+// Checks the + line of a four-line FASTQ record
+pub(crate) fn check_plus_line(line: &[u8]) -> Result<(), String>{
+    if !is_plus_line(line){
+        return Err("FASTQ + line does not start with +. If the sequences span multiple lines, enable multi-line FASTQ parsing.".to_owned());
+    }
+    Ok(())
+}
+
+// Checks that a FASTQ record has as many quality values as bases
+pub(crate) fn check_quality_length(qual_len: usize, seq_len: usize, multiline: bool) -> Result<(), String>{
+    match (qual_len == seq_len, multiline){
+        (true, _) => Ok(()),
+        (false, false) => Err(format!("FASTQ quality line has different length than sequence line ({} vs {})", qual_len, seq_len)),
+        (false, true) => Err(format!("FASTQ quality lines have different total length than sequence lines ({} vs {})", qual_len, seq_len)),
+    }
+}
+
+// Removes the trailing "\n" or "\r\n" from a line read with read_until, if there is one.
+// Returns true if the line ended in "\r\n".
+fn trim_line_end(line: &mut Vec<u8>) -> bool{
+    if line.last() == Some(&b'\n'){
+        line.pop();
+        if line.last() == Some(&b'\r'){
+            line.pop();
+            return true;
+        }
+    }
+    false
+}
+
+impl RecordParser{
+
+    pub(crate) fn new() -> Self{
+        RecordParser{seq_buf: Vec::new(),
+                    head_buf: Vec::new(),
+                    qual_buf: Vec::new(),
+                    plus_buf: Vec::new(),
+                    fasta_temp_buf: Vec::new(),
+                    filetype: FileType::FASTA,
+                    state: State::Header,
+                    position: InputPosition::default(),
+                    saw_crlf: false,
+                    case_policy: CasePolicy::default(),
+                    multiline_fastq: false,}
+    }
+
+    pub(crate) fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.case_policy = case_policy;
+    }
+
+    pub(crate) fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.multiline_fastq = multiline_fastq;
+    }
+
+    pub(crate) fn saw_crlf(&self) -> bool{
+        self.saw_crlf
+    }
+
+    pub(crate) fn position(&self) -> InputPosition{
+        self.position
+    }
+
+    pub(crate) fn seq_buf(&self) -> &[u8]{
+        &self.seq_buf
+    }
+
+    pub(crate) fn head_buf(&self) -> &[u8]{
+        &self.head_buf
+    }
+
+    pub(crate) fn qual_buf(&self) -> &[u8]{
+        &self.qual_buf
+    }
+
+    pub(crate) fn plus_buf(&self) -> &[u8]{
+        &self.plus_buf
+    }
+
+    pub(crate) fn fasta_temp_buf(&self) -> &[u8]{
+        &self.fasta_temp_buf
+    }
+
+    // Whether a line that belongs to the next record has already been read
+    pub(crate) fn has_buffered_line(&self) -> bool{
+        !self.fasta_temp_buf.is_empty()
+    }
+
+    // Clears the previous record and gets ready to parse the next one
+    pub(crate) fn start_record(&mut self, filetype: FileType){
+        self.filetype = filetype;
+        self.seq_buf.clear();
+        self.head_buf.clear();
+        self.qual_buf.clear();
+        self.plus_buf.clear();
+        if self.filetype == FileType::FASTA && !self.fasta_temp_buf.is_empty() {
+            // Take stashed header from previous iteration. It always starts with '>'.
+            self.head_buf.append(&mut self.fasta_temp_buf); // Also clears the temp buf
+            self.state = State::FastaSequence;
+        } else {
+            self.state = State::Header;
+        }
+    }
+
+    // The buffer where the reader should append the next line
+    pub(crate) fn line_buf(&mut self) -> &mut Vec<u8>{
+        match self.state{
+            State::Header => &mut self.head_buf,
+            State::FastaSequence => &mut self.fasta_temp_buf,
+            State::FastqSequence => &mut self.seq_buf,
+            State::FastqPlus | State::FastqMultilineSequence => &mut self.plus_buf, // Temporary storage for each sequence line
+            State::FastqQuality | State::FastqMultilineQuality => &mut self.qual_buf,
+        }
+    }
+
+    // Processes the line that the reader appended to line_buf(). bytes_read is the return value of
+    // read_until, which is 0 at the end of the input. Returns the message of a parse error, which
+    // the reader turns into an Error with its filename and the position of the parser.
+    pub(crate) fn push_line(&mut self, bytes_read: usize) -> Result<Step, String>{
+        self.position.advance(bytes_read);
+        match self.state{
+            State::Header => {
+                if bytes_read == 0 {return Ok(Step::End)} // End of stream
+                check_header(self.filetype, &self.head_buf)?;
+                match self.filetype{
+                    FileType::FASTA => {
+                        self.state = State::FastaSequence;
+                    },
+                    FileType::FASTQ => {
+                        self.saw_crlf |= trim_line_end(&mut self.head_buf);
+                        self.state = if self.multiline_fastq { State::FastqMultilineSequence } else { State::FastqSequence };
+                    },
+                }
+            },
+            State::FastaSequence => {
+                if bytes_read == 0 {
+                    // No more bytes left to read
+                    if self.seq_buf.is_empty(){
+                        // Stream ends with an empty sequence
+                        return Err(EMPTY_FASTA_SEQUENCE.to_owned());
+                    }
+                    return Ok(self.finish_record()); // Ok, last record of the file
+                }
+                if is_fasta_header(&self.fasta_temp_buf){
+                    // Found the header of the next record. Leave it to the buffer for the next iteration.
+                    return Ok(self.finish_record());
+                }
+                // Found more sequence -> Append to self.seq_buf
+                self.seq_buf.append(&mut self.fasta_temp_buf); // Also clears the temp buf
+                self.saw_crlf |= trim_line_end(&mut self.seq_buf); // The last line of the file might not have a newline
+            },
+            State::FastqSequence => {
+                if bytes_read == 0 {
+                    return Err(MISSING_SEQUENCE_LINE.to_owned()); // File can't end here
+                }
+                self.saw_crlf |= trim_line_end(&mut self.seq_buf);
+                self.state = State::FastqPlus;
+            },
+            State::FastqPlus => {
+                if bytes_read == 0 {
+                    return Err(MISSING_PLUS_LINE.to_owned()); // File can't end here
+                }
+                check_plus_line(&self.plus_buf)?;
+                self.state = State::FastqQuality;
+            },
+            State::FastqQuality => {
+                if bytes_read == 0 {
+                    return Err(MISSING_QUALITY_LINE.to_owned()); // File can't end here
+                }
+                self.saw_crlf |= trim_line_end(&mut self.qual_buf); // The last line of the file might not have a newline
+                check_quality_length(self.qual_buf.len(), self.seq_buf.len(), false)?;
+                return Ok(self.finish_record());
+            },
+            State::FastqMultilineSequence => {
+                if bytes_read == 0 {
+                    return Err(MISSING_PLUS_LINE.to_owned()); // File can't end here
+                }
+                if is_plus_line(&self.plus_buf) {
+                    // Found the + line. There is always at least one quality line, even if the sequence is empty.
+                    self.state = State::FastqMultilineQuality;
+                } else {
+                    self.saw_crlf |= trim_line_end(&mut self.plus_buf);
+                    self.seq_buf.append(&mut self.plus_buf); // Also clears the plus buf
+                }
+            },
+            State::FastqMultilineQuality => {
+                if bytes_read == 0 {
+                    return Err(MISSING_QUALITY_LINE.to_owned()); // File can't end here
+                }
+                self.saw_crlf |= trim_line_end(&mut self.qual_buf);
+                if self.qual_buf.len() >= self.seq_buf.len() {
+                    check_quality_length(self.qual_buf.len(), self.seq_buf.len(), true)?;
+                    return Ok(self.finish_record());
+                }
+            },
+        }
+        Ok(Step::NeedLine)
+    }
+
+    fn finish_record(&mut self) -> Step{
+        self.case_policy.apply(&mut self.seq_buf);
+        if self.filetype == FileType::FASTA {
+            // Trim '\n' from the header. The FASTQ header was trimmed when it was read.
+            self.saw_crlf |= trim_line_end(&mut self.head_buf);
+        }
+        self.position.record_index += 1;
+        Step::Record
+    }
+
+    // The record completed by the last push_line
+    pub(crate) fn record(&mut self) -> MutRefRecord<'_>{
+        MutRefRecord{head: &mut self.head_buf[1..], // Remove '>' or '@'
+                    seq: &mut self.seq_buf, // Newlines are already trimmed before
+                    qual: match self.filetype{
+                        FileType::FASTA => None,
+                        FileType::FASTQ => Some(&mut self.qual_buf),
+                    }}
+    }
+}
@@ -0,0 +1,190 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+
+use crate::Error;
+use crate::batch::{fill_batch, RecordBatch};
+use crate::reader::SeqStream;
+use crate::writer::SeqRecordWriter;
+
+// Runs the usual read-process-write loop on multiple threads: one thread parses batches of records
+// from the input stream, worker threads transform each batch into a batch of output records, and
+// the calling thread writes the output batches in the same order as the input batches.
+//
+// The number of batches in memory is bounded by queue_size, so a slow writer or a slow worker
+// makes the reader wait instead of buffering the whole input. If any stage returns an error,
+// the other stages stop and the first error is returned. A panic in a worker is propagated
+// to the calling thread.
+#[derive(Clone, Debug)]
+pub struct Pipeline{
+    n_threads: usize,
+    batch_records: usize,
+    batch_bytes: usize,
+    queue_size: usize,
+}
+
+impl Default for Pipeline{
+    fn default() -> Self {
+        Self{
+            n_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            batch_records: 10_000,
+            batch_bytes: 1 << 20,
+            queue_size: 0, // 0 means 2 * n_threads
+        }
+    }
+}
+
+// A message from the reader or a worker to the writer. The batches are not boxed
+// because that would allocate for every batch.
+#[allow(clippy::large_enum_variant)]
+enum Message{
+    Done{batch_index: u64, input: RecordBatch, output: RecordBatch},
+    Failed(Error),
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl Pipeline{
+
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    // Number of worker threads. The default is the number of available CPUs.
+    pub fn n_threads(mut self, n_threads: usize) -> Self{
+        self.n_threads = n_threads.max(1);
+        self
+    }
+
+    // Maximum number of records in an input batch. The default is 10000.
+    pub fn batch_records(mut self, batch_records: usize) -> Self{
+        self.batch_records = batch_records.max(1);
+        self
+    }
+
+    // Maximum number of bytes in an input batch, not counting the last record. The default is 1 MiB.
+    pub fn batch_bytes(mut self, batch_bytes: usize) -> Self{
+        self.batch_bytes = batch_bytes;
+        self
+    }
+
+    // Maximum number of input batches being read, processed or waiting to be written at the same time.
+    // The default is twice the number of threads.
+    pub fn queue_size(mut self, queue_size: usize) -> Self{
+        self.queue_size = queue_size;
+        self
+    }
+
+    // Reads all records from the input, calls process on each batch of input records from
+    // one of the worker threads, and writes the records that process pushed into the output
+    // batch. The output batch is empty when process is called. Returns the number of records written.
+    pub fn run<S, W, F>(&self, input: &mut S, output: &mut W, process: F) -> Result<u64, Error>
+    where
+        S: SeqStream + Send,
+        W: SeqRecordWriter + ?Sized,
+        F: Fn(&RecordBatch, &mut RecordBatch) -> Result<(), Error> + Sync,
+    {
+        let queue_size = if self.queue_size == 0 { 2 * self.n_threads } else { self.queue_size };
+
+        std::thread::scope(|scope| {
+            let (work_sender, work_receiver) = sync_channel::<(u64, RecordBatch)>(queue_size);
+            let (message_sender, message_receiver) = sync_channel::<Message>(queue_size);
+            let (free_input_sender, free_input_receiver) = channel::<RecordBatch>();
+            let (free_output_sender, free_output_receiver) = channel::<RecordBatch>();
+            for _ in 0..queue_size {
+                free_input_sender.send(RecordBatch::new()).unwrap(); // Receiver is alive
+            }
+
+            let reader_messages = message_sender.clone();
+            scope.spawn(move || self.read_batches(input, free_input_receiver, work_sender, reader_messages));
+
+            let work_receiver = Arc::new(Mutex::new(work_receiver));
+            let free_output_receiver = Arc::new(Mutex::new(free_output_receiver));
+            let process = &process;
+            for _ in 0..self.n_threads {
+                let messages = message_sender.clone();
+                let work_receiver = Arc::clone(&work_receiver);
+                let free_output_receiver = Arc::clone(&free_output_receiver);
+                scope.spawn(move || Self::process_batches(&work_receiver, &free_output_receiver, messages, process));
+            }
+            // Drop the copies held by this thread, so that the message channel closes when the reader and all workers are done
+            drop(message_sender);
+            drop(work_receiver);
+            drop(free_output_receiver);
+
+            // Dropping the channel endpoints when returning makes the other threads stop
+            Self::write_batches(output, message_receiver, free_input_sender, free_output_sender)
+        })
+    }
+
+    fn read_batches<S: SeqStream>(&self, input: &mut S, free_inputs: Receiver<RecordBatch>, work: SyncSender<(u64, RecordBatch)>, messages: SyncSender<Message>){
+        let mut batch_index = 0_u64;
+        // Waiting for a free batch is what bounds the memory use
+        while let Ok(mut batch) = free_inputs.recv() {
+            match fill_batch(input, &mut batch, self.batch_records, self.batch_bytes){
+                Ok(0) => return, // End of input
+                Ok(_) => {
+                    if work.send((batch_index, batch)).is_err() {
+                        return; // The workers have stopped
+                    }
+                    batch_index += 1;
+                },
+                Err(e) => {
+                    let _ = messages.send(Message::Failed(e));
+                    return;
+                },
+            }
+        }
+    }
+
+    fn process_batches<F>(work: &Mutex<Receiver<(u64, RecordBatch)>>, free_outputs: &Mutex<Receiver<RecordBatch>>, messages: SyncSender<Message>, process: &F)
+    where F: Fn(&RecordBatch, &mut RecordBatch) -> Result<(), Error>
+    {
+        loop {
+            // The lock guard is dropped at the end of the statement, so other workers can receive while this one works
+            let received = work.lock().map(|receiver| receiver.recv());
+            let (batch_index, input) = match received {
+                Ok(Ok(work_item)) => work_item,
+                _ => return, // No more input, or another worker panicked while holding the lock
+            };
+            let mut output = free_outputs.lock().ok().and_then(|receiver| receiver.try_recv().ok()).unwrap_or_default();
+            output.clear();
+
+            let message = match std::panic::catch_unwind(AssertUnwindSafe(|| process(&input, &mut output))){
+                Ok(Ok(())) => Message::Done{batch_index, input, output},
+                Ok(Err(e)) => Message::Failed(e),
+                Err(panic) => Message::Panicked(panic),
+            };
+            let stop = !matches!(message, Message::Done{..});
+            if messages.send(message).is_err() || stop {
+                return; // The writer has stopped, or this worker failed
+            }
+        }
+    }
+
+    fn write_batches<W: SeqRecordWriter + ?Sized>(output: &mut W, messages: Receiver<Message>, free_inputs: Sender<RecordBatch>, free_outputs: Sender<RecordBatch>) -> Result<u64, Error>{
+        let mut pending = BTreeMap::<u64, (RecordBatch, RecordBatch)>::new(); // Batches that finished out of order
+        let mut next_batch_index = 0_u64;
+        let mut n_written = 0_u64;
+        for message in messages {
+            match message {
+                Message::Done{batch_index, input, output: batch} => { pending.insert(batch_index, (input, batch)); },
+                Message::Failed(e) => return Err(e),
+                Message::Panicked(panic) => std::panic::resume_unwind(panic),
+            }
+            while let Some((input, batch)) = pending.remove(&next_batch_index) {
+                for rec in batch.iter() {
+                    output.write_ref_record(&rec)?;
+                }
+                n_written += batch.len() as u64;
+                next_batch_index += 1;
+                // The other threads may have stopped already, in which case the batches are just dropped
+                let _ = free_inputs.send(input);
+                let _ = free_outputs.send(batch);
+            }
+        }
+        output.flush()?;
+        Ok(n_written)
+    }
+}
@@ -1,40 +1,156 @@
 use ex::fs::File; // File streams that include the filename in the error messages
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
+#[cfg(feature = "gzip")]
 use flate2::read::MultiGzDecoder;
 use crate::seq_db::SeqDB;
-use crate::{FileType};
+use crate::batch::RecordBatch;
+#[cfg(feature = "bgzf-parallel")]
+use crate::bgzf::BgzfReader;
+use crate::{FileType, Error};
 use crate::record::{MutRefRecord, OwnedRecord, RefRecord};
+use crate::parser::{RecordParser, Step};
 
 // Takes a BufRead because we need read_until.
 pub struct StaticFastXReader<R: std::io::BufRead>{
     pub filetype: FileType,
     pub filename: Option<String>, // Only used for error messages. If None, the file is unknown or there is no file, like when reading from stdin.
     pub input: R,
-    pub seq_buf: Vec<u8>,
-    pub head_buf: Vec<u8>,
-    pub qual_buf: Vec<u8>,
-    pub plus_buf: Vec<u8>, // For the fastq plus-line
-    pub fasta_temp_buf: Vec<u8>, // Stores the fasta header read in the previous iteration
+    parser: RecordParser, // Holds the buffers of the current record
+}
+
+// What to do with the case of the letters in the sequences
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum CasePolicy{
+    Keep, // Keep the case as in the input, e.g. to preserve soft-masking
+    #[default]
+    Upper,
+    Lower,
+}
+
+impl CasePolicy{
+    pub(crate) fn apply(self, seq: &mut [u8]){
+        match self{
+            CasePolicy::Keep => (), // No pass over the sequence needed
+            CasePolicy::Upper => seq.make_ascii_uppercase(),
+            CasePolicy::Lower => seq.make_ascii_lowercase(),
+        }
+    }
+
+    // Whether apply would change any of the letters
+    #[cfg(feature = "mmap")]
+    pub(crate) fn changes(self, seq: &[u8]) -> bool{
+        match self{
+            CasePolicy::Keep => false,
+            CasePolicy::Upper => seq.iter().any(u8::is_ascii_lowercase),
+            CasePolicy::Lower => seq.iter().any(u8::is_ascii_uppercase),
+        }
+    }
+}
+
+// Keeps track of where we are in the uncompressed input stream
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct InputPosition{
+    pub(crate) line: u64, // 1-based number of the line read last. Zero if no lines have been read yet.
+    pub(crate) line_start: u64, // Byte offset of the start of the line read last
+    pub(crate) byte_offset: u64, // Number of bytes consumed from the input
+    pub(crate) record_index: u64, // 0-based index of the record being parsed
+}
+
+impl InputPosition{
+    // Call this after each read_until with the number of bytes read
+    pub(crate) fn advance(&mut self, bytes_read: usize){
+        if bytes_read > 0 {
+            self.line += 1;
+            self.line_start = self.byte_offset;
+            self.byte_offset += bytes_read as u64;
+        }
+    }
 }
 
 pub trait SeqStream{
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>>;
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>;
+
+    // Calls f on each remaining record of the stream. Stops at the first error.
+    fn for_each<F: FnMut(RefRecord<'_>)>(&mut self, mut f: F) -> Result<(), Error> where Self: Sized{
+        while let Some(rec) = self.read_next()?{
+            f(rec);
+        }
+        Ok(())
+    }
+
+    // Calls f on each remaining record of the stream, until f returns an error or reading fails.
+    // The error type of f must be able to hold a jseqio::Error, like Box<dyn std::error::Error> does.
+    fn try_for_each<E, F>(&mut self, mut f: F) -> Result<(), E> where Self: Sized, E: From<Error>, F: FnMut(RefRecord<'_>) -> Result<(), E>{
+        while let Some(rec) = self.read_next()?{
+            f(rec)?;
+        }
+        Ok(())
+    }
+
+    // Turns the stream into an Iterator that copies each record into an OwnedRecord. This allocates
+    // for every record, so read_next or for_each is faster when the records do not need to be kept.
+    fn into_owned_iter(self) -> OwnedRecordIter<Self> where Self: Sized{
+        OwnedRecordIter{stream: self, done: false}
+    }
 }
 
+// A stream that can also give the records as mutable, so that they can be modified in place
+// without copying, for example by SeqStreamExt::map_mut.
+pub trait SeqStreamMut: SeqStream{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>;
+}
+
+// An Iterator over copies of the records of a stream, created by SeqStream::into_owned_iter.
+// The iterator ends after the first error, because the stream can not continue from an
+// arbitrary point after a parse or I/O error.
+pub struct OwnedRecordIter<S: SeqStream>{
+    stream: S,
+    done: bool, // The end of the stream or an error has been returned
+}
+
+impl<S: SeqStream> OwnedRecordIter<S>{
+    pub fn into_inner(self) -> S{
+        self.stream
+    }
+}
+
+impl<S: SeqStream> Iterator for OwnedRecordIter<S>{
+    type Item = Result<OwnedRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item>{
+        if self.done {
+            return None;
+        }
+        let item = self.stream.read_next().map(|rec| rec.map(|rec| rec.to_owned())).transpose();
+        self.done = !matches!(item, Some(Ok(_)));
+        item
+    }
+}
+
+impl<S: SeqStream> std::iter::FusedIterator for OwnedRecordIter<S>{}
+
 // Trait for a stream returning RefRecord objects, used in DynamicFastXReader to abstract over
 // The input stream type.
 trait JSeqIOReaderInterface{
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>>;
-    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>>;
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>;
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>;
 
     // Since we want to call this for trait objects where we don't know the size of the struct,
     // We need to take self in a Box.
-    fn into_db_boxed(self: Box<Self>) -> Result<crate::seq_db::SeqDB, Box<dyn std::error::Error>>;
-    fn into_db_with_revcomp_boxed(self: Box<Self>) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Box<dyn std::error::Error>>;
+    fn into_db_boxed(self: Box<Self>) -> Result<crate::seq_db::SeqDB, Error>;
+    fn into_db_with_revcomp_boxed(self: Box<Self>) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Error>;
 
     fn filetype(&self)-> FileType; 
 
+    fn saw_crlf(&self) -> bool;
+
+    fn is_at_end(&mut self) -> Result<bool, Error>;
+
+    fn set_case_policy(&mut self, case_policy: CasePolicy);
+
+    fn set_multiline_fastq(&mut self, multiline_fastq: bool);
+
     // For error messages
     fn set_filepath(&mut self, filepath: &Path);
 }
@@ -44,188 +160,150 @@ pub struct ParseError{
     pub message: String,
     pub filename: Option<String>,
     pub filetype: Option<FileType>,
+    pub line: u64, // 1-based line number in the uncompressed data
+    pub byte_offset: u64, // Byte offset of the start of the line in the uncompressed data
+    pub record_index: u64, // 0-based index of the record where the error happened
+}
+
+impl ParseError{
+    // An error at the very start of the input, before any records have been parsed
+    fn at_start(message: &str) -> Self{
+        ParseError{message: message.to_owned(), filename: None, filetype: None, line: 1, byte_offset: 0, record_index: 0}
+    }
 }
 
 impl std::error::Error for ParseError{}
 
+// Prints for example: reads.fq.gz:1234567 (record 308641): FASTQ quality line has different length...
 impl std::fmt::Display for ParseError{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        let filename = self.filename.as_deref().unwrap_or("<input>");
+        write!(f, "{}:{} (record {}): {}", filename, self.line, self.record_index, self.message)
     }
 }
 
 impl<R: std::io::BufRead> StaticFastXReader<R>{
 
-    fn build_parse_error(&self, message: &str) -> Box<ParseError>{
-        Box::new(
+    fn build_parse_error(&self, message: &str) -> Error{
+        let position = self.parser.position();
+        Error::Parse(
             ParseError{
                 message: message.to_owned(), 
                 filename: self.filename.clone(), 
-                filetype: Some(self.filetype)
+                filetype: Some(self.filetype),
+                line: position.line,
+                byte_offset: position.line_start,
+                record_index: position.record_index,
             }
         )
     }
 
-    fn read_fasta_record(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>>{
-        self.seq_buf.clear();
-        self.head_buf.clear();
-
-        // Read header line
-        if self.fasta_temp_buf.is_empty() {
-            // This is the first record -> read header from input
-            let bytes_read = self.input.read_until(b'\n', &mut self.head_buf)?;
-            if bytes_read == 0 {return Ok(None)} // End of stream
-        } else{
-            // Take stashed header from previous iteration
-            self.head_buf.append(&mut self.fasta_temp_buf); // Also clears the temp buf
-        }
+    // Read one record from the input.
+    // This is not named just next() because it's not a Rust iterator because it streams the input.
+    pub fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
+        self.read_next_mut().map(|opt| opt.map(|rec| rec.into_shared_ref()))
+    }
 
-        // Read sequence line
+    // Read one record from the input.
+    // This is not named just next() because it's not a Rust iterator because it streams the input.
+    pub fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error> {
+        self.parser.start_record(self.filetype);
         loop{
-            let bytes_read = self.input.read_until(b'\n', &mut self.fasta_temp_buf)?;
-            if bytes_read == 0 {
-                // No more bytes left to read
-                if self.seq_buf.is_empty(){
-                    // Stream ends with an empty sequence
-                    return Err(self.build_parse_error("Empty sequence in FASTA file"));
-                }
-                break; // Ok, last record of the file
-            }
-
-            // Check if we read the header of the next read
-            let start = self.fasta_temp_buf.len() as isize - bytes_read as isize;
-            if self.fasta_temp_buf[start as usize] == b'>'{
-                // Found a header. Leave it to the buffer for the next iteration.
-                break;
-            } else{
-                // Found more sequence -> Append to self.seq_buf
-                self.seq_buf.append(&mut self.fasta_temp_buf); // Also clears the temp buf
-                self.seq_buf.pop(); // Trim newline (TODO: what if there is none?)
+            let bytes_read = self.input.read_until(b'\n', self.parser.line_buf())?;
+            match self.parser.push_line(bytes_read).map_err(|message| self.build_parse_error(&message))?{
+                Step::NeedLine => (),
+                Step::Record => return Ok(Some(self.parser.record())),
+                Step::End => return Ok(None),
             }
         }
+    }
 
-        // Make sure all letters are in same case
-        for c in self.seq_buf.iter_mut() {
-            c.make_ascii_uppercase();
-        }
+    // Clears the batch and fills it with the next records, until the batch has max_records records
+    // or at least max_bytes bytes of headers, sequences and quality values. Returns the number
+    // of records read, which is 0 only at the end of the input.
+    pub fn read_batch(&mut self, batch: &mut RecordBatch, max_records: usize, max_bytes: usize) -> Result<usize, Error>{
+        crate::batch::fill_batch(self, batch, max_records, max_bytes)
+    }
 
-        // Trim '>' and '\n' from the header 
-        assert!(self.head_buf[0] == b'>');
-        assert!(*self.head_buf.last().unwrap() == b'\n');
-        let head_buf_len = self.head_buf.len();
-        let head = &mut self.head_buf.as_mut_slice()[1..head_buf_len-1]; // Remove '>' and '\n'
+    // New with known format
+    fn new_with_format(input: R, filetype: FileType) -> Self{
+        StaticFastXReader{filetype,
+                    input,
+                    filename: None,
+                    parser: RecordParser::new(),}
+    }
 
-        Ok(Some(MutRefRecord{head: head, 
-                            seq: self.seq_buf.as_mut_slice(), // Newlines are already trimmed before
-                            qual: None}))
+    // Allows FASTQ records where the sequence and quality values are wrapped over multiple lines.
+    // This is off by default because the four-line format is faster to parse.
+    pub fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.parser.set_multiline_fastq(multiline_fastq);
     }
 
-    fn read_fastq_record(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>>{
+    // Sets the case conversion applied to the sequences. The default is CasePolicy::Upper.
+    pub fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.parser.set_case_policy(case_policy);
+    }
 
-        self.seq_buf.clear();
-        self.head_buf.clear();
-        self.qual_buf.clear();
-        self.plus_buf.clear();
+    // Whether any of the lines read so far ended in "\r\n" (Windows line endings).
+    // The "\r" characters are always stripped from the records.
+    pub fn saw_crlf(&self) -> bool{
+        self.parser.saw_crlf()
+    }
 
-        // Read header line
-        let bytes_read = self.input.read_until(b'\n', &mut self.head_buf)?;
-        if bytes_read == 0 {return Ok(None)} // End of stream
-        if self.head_buf[0] != b'@'{
-            return Err(self.build_parse_error("FASTQ header line does not start with @"));
-        }
-        assert!(self.head_buf.last().unwrap() == &b'\n');
-        self.head_buf.pop(); // Trim the '\n'
+    // Returns true if the input has been consumed completely. If this returns false,
+    // the next call to read_next returns either a record or an error.
+    pub fn is_at_end(&mut self) -> Result<bool, Error>{
+        // A FASTA header read in the previous iteration may be waiting in the parser
+        Ok(!self.parser.has_buffered_line() && self.input.fill_buf()?.is_empty())
+    }
 
-        // Read sequence line
-        let bytes_read = self.input.read_until(b'\n', &mut self.seq_buf)?;
-        if bytes_read == 0 {
-            return Err(self.build_parse_error("FASTQ sequence line missing.")); // File can't end here
-        }
-        assert!(self.seq_buf.last().unwrap() == &b'\n');
-        self.seq_buf.pop(); // Trim the '\n'
-        
-        // read +-line
-        let bytes_read = self.input.read_until(b'\n', &mut self.plus_buf)?;
-        if bytes_read == 0 {
-            return Err(self.build_parse_error("FASTQ + line missing.")); // File can't end here
-        }
+    // 1-based number of the line read last, or zero if nothing has been read yet.
+    pub fn line_number(&self) -> u64{
+        self.parser.position().line
+    }
 
-        // read qual-line
-        let bytes_read = self.input.read_until(b'\n', &mut self.qual_buf)?;
-        if bytes_read == 0 { // File can't end here
-            return Err(self.build_parse_error("FASTQ quality line missing.")); // File can't end here
-        } 
-        assert!(self.qual_buf.last().unwrap() == &b'\n');
-        self.qual_buf.pop(); // Trim the '\n'
-        if self.qual_buf.len() != self.seq_buf.len() {
-            let msg = format!("FASTQ quality line has different length than sequence line ({} vs {})", self.qual_buf.len(), self.seq_buf.len());
-            return Err(self.build_parse_error(&msg));
-        }
+    // Number of bytes consumed from the uncompressed input stream.
+    pub fn byte_offset(&self) -> u64{
+        self.parser.position().byte_offset
+    }
 
-        // Make sure all letters are in same case
-        for c in self.seq_buf.iter_mut() {
-            c.make_ascii_uppercase();
-        }
+    // Number of records read so far, which is also the 0-based index of the next record.
+    pub fn record_index(&self) -> u64{
+        self.parser.position().record_index
+    }
 
-        assert!(self.head_buf[0] == b'@');
-        Ok(Some(MutRefRecord{head: &mut self.head_buf[1..], // Remove '@'
-                            seq: &mut self.seq_buf,
-                            qual: Some(&mut self.qual_buf)}))
+    // The buffers of the parser, which used to be public fields of the reader. The head,
+    // sequence and quality buffers hold the last record read without the line ends, and
+    // the head buffer starts with the '>' or '@'.
+    pub fn seq_buf(&self) -> &[u8]{
+        self.parser.seq_buf()
     }
 
-    // Read one record from the input.
-    // This is not named just next() because it's not a Rust iterator because it streams the input.
-    pub fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>> {
-        match self.filetype{
-            FileType::FASTA => self.read_fasta_record().map(|opt| opt.map(|rec| rec.into_shared_ref())),
-            FileType::FASTQ => self.read_fastq_record().map(|opt| opt.map(|rec| rec.into_shared_ref())),
-        }
+    pub fn head_buf(&self) -> &[u8]{
+        self.parser.head_buf()
     }
 
-    // Read one record from the input.
-    // This is not named just next() because it's not a Rust iterator because it streams the input.
-    pub fn read_next_mut(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>> {
-        match self.filetype{
-            FileType::FASTA => self.read_fasta_record(),
-            FileType::FASTQ => self.read_fastq_record(),
-        }
+    pub fn qual_buf(&self) -> &[u8]{
+        self.parser.qual_buf()
     }
 
-    // New with known format
-    fn new_with_format(input: R, filetype: FileType) -> Self{
-        StaticFastXReader{filetype,
-                    input,
-                    filename: None,
-                    seq_buf: Vec::<u8>::new(),
-                    head_buf: Vec::<u8>::new(),
-                    qual_buf: Vec::<u8>::new(),
-                    plus_buf: Vec::<u8>::new(),
-                    fasta_temp_buf: Vec::<u8>::new(),}
+    // The plus line of the last four-line FASTQ record, including the line end
+    pub fn plus_buf(&self) -> &[u8]{
+        self.parser.plus_buf()
     }
 
-    // Detect whether it's fasta or FASTQ based on the first byte.
-    pub fn new(mut input: R) -> Result<Self, Box<dyn std::error::Error>>{
-        let bytes = input.fill_buf()?;
-
-        // Empty file is arbitrarily considered as FASTA
-        let mut filetype = FileType::FASTA;
-
-        if !bytes.is_empty(){
-            filetype = match bytes[0]{
-                b'>' => FileType::FASTA,
-                b'@' => FileType::FASTQ,
-                _ => return Err(
-                        Box::new(ParseError{message: "Error: File does not start with '>' or '@'".to_owned(), 
-                        filename: None, 
-                        filetype: None}))
-            } 
-        }
+    // The FASTA header read ahead from the next record, or empty if there is none
+    pub fn fasta_temp_buf(&self) -> &[u8]{
+        self.parser.fasta_temp_buf()
+    }
 
+    // Detect whether it's fasta or FASTQ based on the first byte.
+    pub fn new(mut input: R) -> Result<Self, Error>{
+        let filetype = detect_filetype(input.fill_buf()?)?;
         Ok(StaticFastXReader::new_with_format(input, filetype))
-
     }
 
-    pub fn into_db_with_revcomp(mut self) -> Result<(SeqDB, SeqDB), Box<dyn std::error::Error>>{
+    pub fn into_db_with_revcomp(mut self) -> Result<(SeqDB, SeqDB), Error>{
 
         // Reusable record for storing the reverse complement
         let mut rc_record = 
@@ -263,7 +341,7 @@ impl<R: std::io::BufRead> StaticFastXReader<R>{
         Ok((fw_db, rc_db))
     }
 
-    pub fn into_db(mut self) -> Result<crate::seq_db::SeqDB, Box<dyn std::error::Error>>{
+    pub fn into_db(mut self) -> Result<crate::seq_db::SeqDB, Error>{
         let mut db = SeqDB::new();
 
         while let Some(rec) = self.read_next()?{
@@ -288,78 +366,76 @@ pub struct DynamicFastXReader {
 impl DynamicFastXReader {
  
     // New from file
-    pub fn from_file<P: AsRef<std::path::Path>>(filepath: &P) -> Result<Self, Box<dyn std::error::Error>> {
-        let input = File::open(filepath).unwrap();
-        let mut reader = Self::new(BufReader::new(input))?;
-        reader.stream.set_filepath(filepath.as_ref());
-        Ok(reader)
+    pub fn from_file<P: AsRef<std::path::Path>>(filepath: &P) -> Result<Self, Error> {
+        ReaderBuilder::new().from_file(filepath)
     }
 
     // New from stdin
-    pub fn from_stdin() -> Result<Self, Box<dyn std::error::Error>> {
-        let input = std::io::stdin();
-        let reader = Self::new(BufReader::new(input))?;
-        Ok(reader)
+    pub fn from_stdin() -> Result<Self, Error> {
+        ReaderBuilder::new().from_stdin()
     }
 
     // New from stream, with automatic gzip detection
-    pub fn new<R: std::io::BufRead + 'static + Send>(mut input: R) -> Result<Self, Box<dyn std::error::Error>>{
-        let bytes = input.fill_buf()?;
-        let mut gzipped = false;
-        match bytes.len(){
-            0 => (), // Empty file
-            1 => return Err(Box::new(ParseError{message: "Corrupt FASTA/FASTQ file: only one byte found.".to_owned(), 
-                        filename: None, 
-                        filetype: None})),
-            _ => { // Two or more bytes available. Check if the first two are a valid gzip header.
-                if bytes[0] == 0x1f && bytes[1] == 0x8b{ 
-                    gzipped = true;
-                }
-            }
-        }
-
-        match gzipped{
-            true => {
-                let gzdecoder = MultiGzDecoder::<R>::new(input);
-
-                // We wrap this in BufReader because the FastX parser requires buffered reading
-                let gzbufdecoder = BufReader::<MultiGzDecoder::<R>>::new(gzdecoder);
-                Self::from_raw_stream(gzbufdecoder, crate::CompressionType::Gzip)
-            },
-            false => Self::from_raw_stream(input, crate::CompressionType::None)
-        }
+    pub fn new<R: std::io::BufRead + 'static + Send>(input: R) -> Result<Self, Error>{
+        ReaderBuilder::new().build(input)
     }
 
-    // Creates a reader from a raw stream of uncompressed data (no gzip detection). Used by other constructors.
-    // Need to constrain + 'static because boxed trait objects always need to have a static lifetime.
-    fn from_raw_stream<R: std::io::BufRead + 'static + Send>(r: R, compression_type: crate::CompressionType) -> Result<Self, Box<dyn std::error::Error>>{
-        let reader = StaticFastXReader::<R>::new(r)?;
-        Ok(DynamicFastXReader {stream: Box::new(reader), compression_type})
-    }
-
-    pub fn into_db(self) -> Result<crate::seq_db::SeqDB, Box<dyn std::error::Error>>{
-        self.stream.into_db_boxed()
+    pub fn into_db(self) -> Result<crate::seq_db::SeqDB, Error>{
+        let compression_type = self.compression_type;
+        self.stream.into_db_boxed().map_err(|e| e.with_compression(compression_type))
     }
 
-    pub fn into_db_with_revcomp(self) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Box<dyn std::error::Error>>{
-        self.stream.into_db_with_revcomp_boxed()
+    pub fn into_db_with_revcomp(self) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Error>{
+        let compression_type = self.compression_type;
+        self.stream.into_db_with_revcomp_boxed().map_err(|e| e.with_compression(compression_type))
     }
 
     pub fn compression_type(&self) -> crate::CompressionType{
         self.compression_type
     }
 
-    pub fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>>{
-        self.stream.read_next()
+    pub fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        let compression_type = self.compression_type;
+        self.stream.read_next().map_err(|e| e.with_compression(compression_type))
     }
 
-    pub fn read_next_mut(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>>{
-        self.stream.read_next_mut()
+    pub fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        let compression_type = self.compression_type;
+        self.stream.read_next_mut().map_err(|e| e.with_compression(compression_type))
+    }
+
+    // Clears the batch and fills it with the next records, until the batch has max_records records
+    // or at least max_bytes bytes of headers, sequences and quality values. Returns the number
+    // of records read, which is 0 only at the end of the input.
+    pub fn read_batch(&mut self, batch: &mut RecordBatch, max_records: usize, max_bytes: usize) -> Result<usize, Error>{
+        crate::batch::fill_batch(self, batch, max_records, max_bytes)
     }
 
     pub fn filetype(&self)-> FileType{
         self.stream.filetype()
     }
+
+    // Whether any of the lines read so far ended in "\r\n" (Windows line endings).
+    pub fn saw_crlf(&self) -> bool{
+        self.stream.saw_crlf()
+    }
+
+    // Returns true if the input has been consumed completely. If this returns false,
+    // the next call to read_next returns either a record or an error.
+    pub fn is_at_end(&mut self) -> Result<bool, Error>{
+        let compression_type = self.compression_type;
+        self.stream.is_at_end().map_err(|e| e.with_compression(compression_type))
+    }
+
+    // Sets the case conversion applied to the sequences. The default is CasePolicy::Upper.
+    pub fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.stream.set_case_policy(case_policy);
+    }
+
+    // Allows FASTQ records where the sequence and quality values are wrapped over multiple lines.
+    pub fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.stream.set_multiline_fastq(multiline_fastq);
+    }
     
     // For error messages
     pub fn set_filepath(&mut self, filepath: &Path){
@@ -368,15 +444,345 @@ impl DynamicFastXReader {
 }
 
 
+// The smallest buffer size that ReaderBuilder::buffer_size accepts. A buffer of zero bytes would
+// look like the end of the input.
+const MIN_BUFFER_SIZE: usize = 64;
+
+// Configures and creates readers. For example:
+//
+// let reader = ReaderBuilder::new().case_policy(CasePolicy::Keep).filetype(FileType::FASTA).from_file(&"genome.fna")?;
+//
+// Options that are not set are detected from the input or left at their defaults,
+// so ReaderBuilder::new().build(input) is the same as DynamicFastXReader::new(input).
+// The build functions take &self, so the same builder can be used to open many files.
+#[derive(Clone, Debug, Default)]
+pub struct ReaderBuilder{
+    buffer_size: Option<usize>,
+    filetype: Option<FileType>,
+    compression_type: Option<crate::CompressionType>,
+    filename: Option<String>,
+    case_policy: CasePolicy,
+    multiline_fastq: bool,
+    decompression_threads: Option<usize>,
+}
+
+impl ReaderBuilder{
+
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    // Capacity of the internal buffers for reading the file and the decompressed stream.
+    // Sizes smaller than 64 bytes are rounded up to 64.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self{
+        self.buffer_size = Some(buffer_size.max(MIN_BUFFER_SIZE));
+        self
+    }
+
+    // Parse the input as this file type instead of detecting it from the first byte.
+    pub fn filetype(mut self, filetype: FileType) -> Self{
+        self.filetype = Some(filetype);
+        self
+    }
+
+    // Decompress the input with this compression type instead of detecting it from the magic bytes.
+    pub fn compression_type(mut self, compression_type: crate::CompressionType) -> Self{
+        self.compression_type = Some(compression_type);
+        self
+    }
+
+    // The filename shown in error messages. By default this is the path given to from_file,
+    // and nothing for other inputs.
+    pub fn filename<S: Into<String>>(mut self, filename: S) -> Self{
+        self.filename = Some(filename.into());
+        self
+    }
+
+    pub fn case_policy(mut self, case_policy: CasePolicy) -> Self{
+        self.case_policy = case_policy;
+        self
+    }
+
+    // Allow FASTQ records wrapped over multiple lines. See StaticFastXReader::set_multiline_fastq.
+    pub fn multiline_fastq(mut self, multiline_fastq: bool) -> Self{
+        self.multiline_fastq = multiline_fastq;
+        self
+    }
+
+    // Number of threads for decompressing BGZF input (blocked gzip, as written by bgzip).
+    // The default is the number of CPUs, up to 4. With 0, BGZF is decompressed like any
+    // other gzip stream, on the thread that reads the records. Plain gzip always uses one thread.
+    // Has no effect if the bgzf-parallel feature is disabled.
+    pub fn decompression_threads(mut self, n_threads: usize) -> Self{
+        self.decompression_threads = Some(n_threads);
+        self
+    }
+
+    pub fn from_file<P: AsRef<Path>>(&self, filepath: &P) -> Result<DynamicFastXReader, Error>{
+        let input = File::open(filepath)?;
+        let input = match self.buffer_size{
+            Some(capacity) => BufReader::with_capacity(capacity, input),
+            None => BufReader::new(input),
+        };
+        match self.filename{
+            Some(_) => self.build(input),
+            None => self.clone().filename(filepath.as_ref().to_string_lossy()).build(input),
+        }
+    }
+
+    pub fn from_stdin(&self) -> Result<DynamicFastXReader, Error>{
+        let input = std::io::stdin();
+        match self.buffer_size{
+            Some(capacity) => self.build(BufReader::with_capacity(capacity, input)),
+            None => self.build(BufReader::new(input)),
+        }
+    }
+
+    // Creates a DynamicFastXReader over any buffered stream, decompressing it if needed.
+    // Need to constrain + 'static because boxed trait objects always need to have a static lifetime.
+    pub fn build<R: BufRead + 'static + Send>(&self, mut input: R) -> Result<DynamicFastXReader, Error>{
+        match self.compression_type{
+            Some(compression_type) => self.build_decompressed(input, compression_type),
+            None => {
+                let prefix = read_prefix(&mut input)?;
+                let compression_type = detect_compression_from_bytes(&prefix).map_err(|e| self.add_filename(e))?;
+                // Put the prefix back in front of the rest of the input
+                self.build_decompressed(std::io::Cursor::new(prefix).chain(input), compression_type)
+            },
+        }
+    }
+
+    // Creates a DynamicFastXReader over input compressed with compression_type
+    #[cfg_attr(not(feature = "bgzf-parallel"), allow(unused_mut))] // The input is peeked for BGZF
+    fn build_decompressed<R: BufRead + 'static + Send>(&self, mut input: R, compression_type: crate::CompressionType) -> Result<DynamicFastXReader, Error>{
+        match compression_type{
+            #[cfg(feature = "gzip")]
+            crate::CompressionType::Gzip => {
+                #[cfg(feature = "bgzf-parallel")]
+                {
+                    let n_threads = self.decompression_threads.unwrap_or_else(default_decompression_threads);
+                    if n_threads > 0 && crate::bgzf::is_bgzf(input.fill_buf()?) {
+                        return self.build_dynamic_from_raw_stream(BgzfReader::new(input, n_threads), compression_type);
+                    }
+                }
+
+                let gzdecoder = MultiGzDecoder::<R>::new(input);
+
+                // We wrap this in BufReader because the FastX parser requires buffered reading
+                let gzbufdecoder = match self.buffer_size{
+                    Some(capacity) => BufReader::with_capacity(capacity, gzdecoder),
+                    None => BufReader::new(gzdecoder),
+                };
+                self.build_dynamic_from_raw_stream(gzbufdecoder, compression_type)
+            },
+            #[cfg(feature = "zstd")]
+            crate::CompressionType::Zstd => {
+                // The zstd decoder does its own buffering of the compressed input
+                let decoder = zstd::stream::read::Decoder::with_buffer(input)?;
+                let decoder = match self.buffer_size{
+                    Some(capacity) => BufReader::with_capacity(capacity, decoder),
+                    None => BufReader::new(decoder),
+                };
+                self.build_dynamic_from_raw_stream(decoder, compression_type)
+            },
+            #[cfg(feature = "bzip2")]
+            crate::CompressionType::Bzip2 => {
+                let decoder = bzip2::bufread::MultiBzDecoder::new(input);
+                let decoder = match self.buffer_size{
+                    Some(capacity) => BufReader::with_capacity(capacity, decoder),
+                    None => BufReader::new(decoder),
+                };
+                self.build_dynamic_from_raw_stream(decoder, compression_type)
+            },
+            #[cfg(feature = "xz")]
+            crate::CompressionType::Xz => {
+                let decoder = xz2::bufread::XzDecoder::new_multi_decoder(input);
+                let decoder = match self.buffer_size{
+                    Some(capacity) => BufReader::with_capacity(capacity, decoder),
+                    None => BufReader::new(decoder),
+                };
+                self.build_dynamic_from_raw_stream(decoder, compression_type)
+            },
+            crate::CompressionType::None => self.build_dynamic_from_raw_stream(input, compression_type),
+            #[allow(unreachable_patterns)] // Reachable only if some compression features are disabled
+            disabled => Err(Error::CompressionDisabled(disabled)),
+        }
+    }
+
+    // Creates an AsyncFastXReader over a tokio stream, decompressing it if needed. The detection of the
+    // compression and the file type works like in build. The number of decompression threads is not used.
+    #[cfg(feature = "async")]
+    pub async fn build_async<R: tokio::io::AsyncBufRead + Unpin + Send + 'static>(&self, mut input: R) -> Result<crate::async_reader::AsyncFastXReader, Error>{
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+        let (compression_type, mut input) = match self.compression_type{
+            Some(compression_type) => (compression_type, crate::async_reader::decompress(input, compression_type, self.buffer_size)?),
+            None => {
+                // Like read_prefix
+                let mut prefix = Vec::with_capacity(DETECTION_PREFIX_LEN);
+                AsyncReadExt::take(&mut input, DETECTION_PREFIX_LEN as u64).read_to_end(&mut prefix).await?;
+                let compression_type = detect_compression_from_bytes(&prefix).map_err(|e| self.add_filename(e))?;
+                // Put the prefix back in front of the rest of the input
+                (compression_type, crate::async_reader::decompress(AsyncReadExt::chain(std::io::Cursor::new(prefix), input), compression_type, self.buffer_size)?)
+            },
+        };
+
+        let filetype = match self.filetype{
+            Some(filetype) => filetype,
+            None => {
+                let bytes = input.fill_buf().await.map_err(|e| Error::from(e).with_compression(compression_type))?;
+                detect_filetype(bytes).map_err(|e| self.add_filename(e))?
+            },
+        };
+        let mut reader = crate::async_reader::AsyncFastXReader::new_with_format(input, filetype, compression_type);
+        reader.filename = self.filename.clone();
+        reader.set_case_policy(self.case_policy);
+        reader.set_multiline_fastq(self.multiline_fastq);
+        Ok(reader)
+    }
+
+    // Creates a StaticFastXReader over a stream of uncompressed data.
+    // Returns an error if a compression type other than CompressionType::None has been set.
+    pub fn build_static<R: BufRead>(&self, input: R) -> Result<StaticFastXReader<R>, Error>{
+        match self.compression_type{
+            None | Some(crate::CompressionType::None) => (),
+            Some(c) => return Err(Error::Format(format!("StaticFastXReader can not decompress {:?}", c))),
+        }
+        self.build_static_from_raw_stream(input)
+    }
+
+    // Memory-maps an uncompressed file and creates a reader whose records borrow from the mapped file.
+    // Returns an error if the file is compressed or a compression type other than CompressionType::None
+    // has been set. The buffer size and the number of decompression threads are not used.
+    #[cfg(feature = "mmap")]
+    pub fn mmap_file<P: AsRef<Path>>(&self, filepath: &P) -> Result<crate::mmap::MmapFastXReader, Error>{
+        if self.filename.is_none() {
+            return self.clone().filename(filepath.as_ref().to_string_lossy()).mmap_file(filepath);
+        }
+        let file = File::open(filepath)?;
+        // Safety: the caller must not modify the file while it is mapped. See MmapFastXReader.
+        let data = unsafe { memmap2::Mmap::map(&*file)? };
+
+        let compression_type = match self.compression_type{
+            Some(compression_type) => compression_type,
+            None => detect_compression_from_bytes(&data).map_err(|e| self.add_filename(e))?,
+        };
+        if compression_type != crate::CompressionType::None {
+            return Err(Error::Format(format!("{} is compressed with {:?}. Only uncompressed files can be memory-mapped.", filepath.as_ref().display(), compression_type)));
+        }
+        let filetype = match self.filetype{
+            Some(filetype) => filetype,
+            None => detect_filetype(&data).map_err(|e| self.add_filename(e))?,
+        };
+
+        let mut reader = crate::mmap::MmapFastXReader::new(data, filetype, self.filename.clone());
+        reader.set_case_policy(self.case_policy);
+        reader.set_multiline_fastq(self.multiline_fastq);
+        Ok(reader)
+    }
+
+    fn build_static_from_raw_stream<R: BufRead>(&self, input: R) -> Result<StaticFastXReader<R>, Error>{
+        let mut reader = match self.filetype{
+            Some(filetype) => StaticFastXReader::new_with_format(input, filetype),
+            None => StaticFastXReader::new(input).map_err(|e| self.add_filename(e))?,
+        };
+        reader.filename = self.filename.clone();
+        reader.set_case_policy(self.case_policy);
+        reader.set_multiline_fastq(self.multiline_fastq);
+        Ok(reader)
+    }
+
+    fn build_dynamic_from_raw_stream<R: BufRead + 'static + Send>(&self, input: R, compression_type: crate::CompressionType) -> Result<DynamicFastXReader, Error>{
+        let reader = self.build_static_from_raw_stream(input).map_err(|e| e.with_compression(compression_type))?;
+        Ok(DynamicFastXReader {stream: Box::new(reader), compression_type})
+    }
+
+    // Errors from format detection happen before the reader knows its filename
+    fn add_filename(&self, e: Error) -> Error{
+        match e {
+            Error::Parse(mut e) => {
+                e.filename = self.filename.clone();
+                Error::Parse(e)
+            },
+            other => other,
+        }
+    }
+}
+
+// The parser runs on one thread, so more decompression threads than this rarely help
+#[cfg(feature = "bgzf-parallel")]
+fn default_decompression_threads() -> usize{
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(4)
+}
+
+// Detects whether the data is FASTA or FASTQ from the first byte
+pub(crate) fn detect_filetype(bytes: &[u8]) -> Result<FileType, Error>{
+    match bytes.first(){
+        None => Ok(FileType::FASTA), // Empty file is arbitrarily considered as FASTA
+        Some(b'>') => Ok(FileType::FASTA),
+        Some(b'@') => Ok(FileType::FASTQ),
+        Some(_) => Err(Error::Parse(ParseError::at_start("File does not start with '>' or '@'"))),
+    }
+}
+
+// Number of bytes read from the start of the input for detecting the compression. The longest magic
+// number is the 6 bytes of xz, and telling BGZF from plain gzip needs the gzip header up to the BC field.
+pub(crate) const DETECTION_PREFIX_LEN: usize = 18;
+
+// Reads the first DETECTION_PREFIX_LEN bytes of the input, or all of it if it is shorter. A single read,
+// for example from a pipe, may give fewer bytes than the magic numbers have, so this reads until there
+// are enough bytes or the input ends. The bytes are consumed from the input.
+pub(crate) fn read_prefix<R: Read>(input: &mut R) -> std::io::Result<Vec<u8>>{
+    let mut prefix = Vec::with_capacity(DETECTION_PREFIX_LEN);
+    input.by_ref().take(DETECTION_PREFIX_LEN as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+// Decompresses the start of the input on the calling thread and returns its first DETECTION_PREFIX_LEN
+// bytes, for detecting the file type without building a reader.
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz")), allow(unused_mut))] // Nothing to decompress
+pub(crate) fn read_decompressed_prefix<R: BufRead>(mut input: R, compression_type: crate::CompressionType) -> Result<Vec<u8>, Error>{
+    let prefix = match compression_type{
+        #[cfg(feature = "gzip")]
+        crate::CompressionType::Gzip => read_prefix(&mut MultiGzDecoder::new(input)),
+        #[cfg(feature = "zstd")]
+        crate::CompressionType::Zstd => zstd::stream::read::Decoder::with_buffer(input).and_then(|mut decoder| read_prefix(&mut decoder)),
+        #[cfg(feature = "bzip2")]
+        crate::CompressionType::Bzip2 => read_prefix(&mut bzip2::bufread::MultiBzDecoder::new(input)),
+        #[cfg(feature = "xz")]
+        crate::CompressionType::Xz => read_prefix(&mut xz2::bufread::XzDecoder::new_multi_decoder(input)),
+        crate::CompressionType::None => read_prefix(&mut input),
+        #[allow(unreachable_patterns)] // Reachable only if some compression features are disabled
+        disabled => return Err(Error::CompressionDisabled(disabled)),
+    };
+    prefix.map_err(|e| Error::from(e).with_compression(compression_type))
+}
+
+// Detects the compression type from the first bytes of the input, as read by read_prefix.
+// An input shorter than the magic numbers is uncompressed.
+pub(crate) fn detect_compression_from_bytes(bytes: &[u8]) -> Result<crate::CompressionType, Error>{
+    if bytes.starts_with(&[0x1f, 0x8b]){
+        Ok(crate::CompressionType::Gzip)
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]){
+        Ok(crate::CompressionType::Zstd)
+    } else if bytes.starts_with(b"BZh"){
+        Ok(crate::CompressionType::Bzip2)
+    } else if bytes.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]){
+        Ok(crate::CompressionType::Xz)
+    } else {
+        Ok(crate::CompressionType::None)
+    }
+}
+
 // Implement common SeqRecordProducer trait for all
 // StaticFastXReaders over the generic parameter R.
 impl<R: BufRead> JSeqIOReaderInterface for StaticFastXReader<R>{
 
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
         self.read_next()
     }
 
-    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord>, Box<dyn std::error::Error>> {
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error> {
         self.read_next_mut()
     }
 
@@ -384,33 +790,61 @@ impl<R: BufRead> JSeqIOReaderInterface for StaticFastXReader<R>{
         self.filetype
     }
 
-    fn into_db_boxed(self: Box<Self>) -> Result<crate::seq_db::SeqDB, Box<dyn std::error::Error>>{
+    fn saw_crlf(&self) -> bool{
+        self.saw_crlf()
+    }
+
+    fn is_at_end(&mut self) -> Result<bool, Error>{
+        self.is_at_end()
+    }
+
+    fn set_case_policy(&mut self, case_policy: CasePolicy){
+        self.set_case_policy(case_policy);
+    }
+
+    fn set_multiline_fastq(&mut self, multiline_fastq: bool){
+        self.set_multiline_fastq(multiline_fastq);
+    }
+
+    fn into_db_boxed(self: Box<Self>) -> Result<crate::seq_db::SeqDB, Error>{
         self.into_db()
     }
 
-    fn into_db_with_revcomp_boxed(self: Box<Self>) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Box<dyn std::error::Error>>{
+    fn into_db_with_revcomp_boxed(self: Box<Self>) -> Result<(crate::seq_db::SeqDB, crate::seq_db::SeqDB), Error>{
         self.into_db_with_revcomp()
     }    
 
     // For error messages
     fn set_filepath(&mut self, filepath: &Path){
-        self.filename = Some(filepath.as_os_str().to_str().unwrap().to_owned());
+        self.filename = Some(filepath.to_string_lossy().into_owned());
     }
 
 }
 
 impl SeqStream for DynamicFastXReader {
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>> {
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
         DynamicFastXReader::read_next(self)
     }
 }
 
 impl<R: BufRead> SeqStream for StaticFastXReader<R> {
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>> {
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
         StaticFastXReader::read_next(self)
     }
 }
 
+impl SeqStreamMut for DynamicFastXReader {
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error> {
+        DynamicFastXReader::read_next_mut(self)
+    }
+}
+
+impl<R: BufRead> SeqStreamMut for StaticFastXReader<R> {
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error> {
+        StaticFastXReader::read_next_mut(self)
+    }
+}
+
 // Turns a SeqStream into another SeqStream that also streams the reverse
 // complements of the original stream. See read_next() for more information.
 pub struct SeqStreamWithRevComp<S: SeqStream> {
@@ -432,7 +866,8 @@ impl<S: SeqStream> SeqStreamWithRevComp<S> {
 impl<S: SeqStream> SeqStream for SeqStreamWithRevComp<S> {
     // If the original sequence stream is x1,x2,x3..., returns sequences in the order
     // x1, rc(x1), x2, rc(x2), x3, rc(x3)...
-    fn read_next(&mut self) -> Result<Option<RefRecord>, Box<dyn std::error::Error>> {
+    #[allow(clippy::needless_return)]
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error> {
         self.parity = !self.parity;
 
         if self.parity {
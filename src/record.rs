@@ -49,6 +49,7 @@ impl<'a> MutRefRecord<'a>{
         rr.to_owned()
     }
 
+    #[allow(clippy::manual_map)]
     pub fn into_shared_ref(self) -> RefRecord<'a>{
         let qual_reborrow = match self.qual{
             Some(q) => Some(&*q),
@@ -56,9 +57,14 @@ impl<'a> MutRefRecord<'a>{
         };
         RefRecord{head: &*self.head, seq: &*self.seq, qual: qual_reborrow}
     }
+
+    pub fn as_ref_record(&self) -> RefRecord<'_>{
+        RefRecord{head: self.head, seq: self.seq, qual: self.qual.as_deref()}
+    }
 }
 
 impl OwnedRecord{
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn as_ref_record(&self) -> RefRecord{
         RefRecord { 
             head: self.head.as_slice(), 
@@ -70,6 +76,10 @@ impl OwnedRecord{
         }
     }
 
+    pub fn as_mut_ref_record(&mut self) -> MutRefRecord<'_>{
+        MutRefRecord{head: &mut self.head, seq: &mut self.seq, qual: self.qual.as_deref_mut()}
+    }
+
     pub fn reverse_complement(&mut self){
         crate::reverse_complement_in_place(&mut self.seq);
         if let Some(qual) = &mut self.qual{
@@ -94,6 +104,7 @@ impl<'a> Record for MutRefRecord<'a>{
 impl Record for OwnedRecord{
     fn head(&self) -> &[u8]{self.head.as_slice()}
     fn seq(&self) -> &[u8]{self.seq.as_slice()}
+    #[allow(clippy::needless_return)]
     fn qual(&self) -> Option<&[u8]>{
         match &self.qual{
             Some(q) => return Some(q.as_slice()),
@@ -106,11 +117,11 @@ impl<'a> fmt::Display for RefRecord<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f,
                "SeqRecord{{ \n  Head: {}\n  Seq:  {}\n  Qual: {}\n}}", 
-               std::str::from_utf8(self.head).unwrap(),
-               std::str::from_utf8(self.seq).unwrap(),
+               String::from_utf8_lossy(self.head),
+               String::from_utf8_lossy(self.seq),
                match self.qual{
-                   Some(q) => std::str::from_utf8(q).unwrap(),
-                   None => "", // No quality values
+                   Some(q) => String::from_utf8_lossy(q),
+                   None => "".into(), // No quality values
                }
                
         )
@@ -0,0 +1,154 @@
+use std::io::{BufRead, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::Error;
+use crate::adapters::copy_record;
+use crate::reader::{DynamicFastXReader, ReaderBuilder, SeqStream, SeqStreamMut};
+use crate::record::{MutRefRecord, OwnedRecord, RefRecord};
+use crate::seq_db::SeqDB;
+
+// Random subsampling of streams. All samplers take a seed, and give the same records for the
+// same seed and input on every platform. The sampled records are returned in the input order.
+
+// The SplitMix64 generator. It is small and fast, and good enough for choosing records.
+struct Rng{
+    state: u64,
+}
+
+impl Rng{
+    fn new(seed: u64) -> Self{
+        Rng{state: seed}
+    }
+
+    fn next_u64(&mut self) -> u64{
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64{
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    // Uniform in [0, n). The bias of the multiply-shift method is negligible for the counts of records in a file.
+    fn below(&mut self, n: u64) -> u64{
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+// Keeps each record of the stream independently with probability fraction.
+// The number of records kept is random. Panics if fraction is not between 0 and 1.
+pub fn sample_fraction<S: SeqStream>(stream: S, fraction: f64, seed: u64) -> SampleFraction<S>{
+    assert!((0.0..=1.0).contains(&fraction), "Sampling fraction {} is not between 0 and 1", fraction);
+    SampleFraction{inner: stream, fraction, rng: Rng::new(seed), rec: OwnedRecord{head: Vec::new(), seq: Vec::new(), qual: None}}
+}
+
+// Created by sample_fraction. Like adapters::Filter, the kept records are copied into a buffer owned by the sampler.
+pub struct SampleFraction<S>{
+    inner: S,
+    fraction: f64,
+    rng: Rng,
+    rec: OwnedRecord, // The last kept record
+}
+
+impl<S: SeqStream> SeqStream for SampleFraction<S>{
+    fn read_next(&mut self) -> Result<Option<RefRecord<'_>>, Error>{
+        loop{
+            match self.inner.read_next()?{
+                None => return Ok(None),
+                Some(rec) => if self.rng.next_f64() < self.fraction {
+                    copy_record(&rec, &mut self.rec);
+                    break;
+                },
+            }
+        }
+        Ok(Some(self.rec.as_ref_record()))
+    }
+}
+
+impl<S: SeqStream> SeqStreamMut for SampleFraction<S>{
+    fn read_next_mut(&mut self) -> Result<Option<MutRefRecord<'_>>, Error>{
+        match self.read_next()?{
+            None => Ok(None),
+            Some(_) => Ok(Some(self.rec.as_mut_ref_record())),
+        }
+    }
+}
+
+// Chooses n records uniformly at random in one pass over the stream, with reservoir sampling.
+// Keeps n records in memory. Returns all records if the stream has at most n records.
+pub fn reservoir_sample<S: SeqStream>(mut stream: S, n: usize, seed: u64) -> Result<SeqDB, Error>{
+    let mut rng = Rng::new(seed);
+    let mut reservoir = Vec::<(u64, OwnedRecord)>::with_capacity(n); // Records and their indices in the stream
+    let mut index = 0_u64;
+    while let Some(rec) = stream.read_next()?{
+        if reservoir.len() < n {
+            reservoir.push((index, rec.to_owned()));
+        } else {
+            let slot = rng.below(index + 1) as usize;
+            if slot < n {
+                reservoir[slot].0 = index;
+                copy_record(&rec, &mut reservoir[slot].1);
+            }
+        }
+        index += 1;
+    }
+
+    reservoir.sort_unstable_by_key(|(index, _)| *index);
+    let mut db = SeqDB::new();
+    for (_, rec) in reservoir.iter() {
+        db.push_record(rec.as_ref_record());
+    }
+    db.shrink_to_fit();
+    Ok(db)
+}
+
+// Copies n records chosen uniformly at random from the stream of count records into a SeqDB.
+// Uses selection sampling, which needs the number of records but only keeps the chosen records in memory.
+fn select<S: SeqStream>(mut stream: S, count: u64, n: usize, seed: u64) -> Result<SeqDB, Error>{
+    let mut rng = Rng::new(seed);
+    let mut needed = (n as u64).min(count);
+    let mut remaining = count;
+    let mut db = SeqDB::new();
+    while needed > 0 {
+        let rec = match stream.read_next()?{
+            Some(rec) => rec,
+            None => return Err(Error::Format("The input changed between the two passes of sampling".to_owned())),
+        };
+        // Keep the record with probability needed / remaining
+        if rng.below(remaining) < needed {
+            db.push_record(rec);
+            needed -= 1;
+        }
+        remaining -= 1;
+    }
+    db.shrink_to_fit();
+    Ok(db)
+}
+
+fn count_records<S: SeqStream>(mut stream: S) -> Result<u64, Error>{
+    let mut count = 0_u64;
+    while stream.read_next()?.is_some() {
+        count += 1;
+    }
+    Ok(count)
+}
+
+// Chooses n records uniformly at random from uncompressed seekable input, for example a file.
+// The first pass counts the records and the second reads the chosen ones, so only the
+// chosen records are kept in memory. Returns all records if the input has at most n records.
+pub fn sample_exact<R: BufRead + Seek>(mut input: R, n: usize, seed: u64) -> Result<SeqDB, Error>{
+    let start = input.stream_position()?;
+    let count = count_records(ReaderBuilder::new().build_static(&mut input)?)?;
+    input.seek(SeekFrom::Start(start))?;
+    select(ReaderBuilder::new().build_static(&mut input)?, count, n, seed)
+}
+
+// Like sample_exact, but reads the file twice from the start, so the file can also be compressed.
+pub fn sample_exact_from_file<P: AsRef<Path>>(filepath: &P, n: usize, seed: u64) -> Result<SeqDB, Error>{
+    let count = count_records(DynamicFastXReader::from_file(filepath)?)?;
+    select(DynamicFastXReader::from_file(filepath)?, count, n, seed)
+}
@@ -1,5 +1,6 @@
 use crate::record::{MutRefRecord, RefRecord};
 
+#[derive(Debug)]
 pub struct SeqDB {
     headbuf: Vec<u8>,
     seqbuf: Vec<u8>,
@@ -14,7 +15,7 @@ pub struct SeqDB {
 
 impl SeqDB{
 
-    pub fn iter(&self) -> SeqDBIterator {
+    pub fn iter(&self) -> SeqDBIterator<'_> {
         SeqDBIterator{seq_db: self, pos: 0}
     }
 
@@ -23,6 +24,7 @@ impl SeqDB{
         // ^ The -1 is because we have an end sentinel at the end of the head_starts vector
     }
 
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn get_mut(&mut self, seq_index: usize) -> MutRefRecord {
         if seq_index >= self.head_starts.len(){
             panic!("SeqDB: Sequence index {} not found in database containing {} sequences", seq_index, self.sequence_count());
@@ -44,6 +46,7 @@ impl SeqDB{
 
     }
 
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn get(&self, seq_index: usize) -> RefRecord{
         if seq_index >= self.head_starts.len(){
             panic!("SeqDB: Sequence index {} not found in database containing {} sequences", seq_index, self.sequence_count());
@@ -98,6 +101,21 @@ impl SeqDB{
         self.qual_starts.push(self.qualbuf.len()); // Empty quality values
     }
 
+    // Total number of bytes in the headers, sequences and quality values
+    pub fn byte_size(&self) -> usize{
+        self.headbuf.len() + self.seqbuf.len() + self.qualbuf.len()
+    }
+
+    // Removes all sequences but keeps the allocated memory for reuse
+    pub fn clear(&mut self){
+        self.headbuf.clear();
+        self.seqbuf.clear();
+        self.qualbuf.clear();
+        self.head_starts.truncate(1); // Keep the start sentinel 0
+        self.seq_starts.truncate(1);
+        self.qual_starts.truncate(1);
+    }
+
     pub fn shrink_to_fit(&mut self){
         self.headbuf.shrink_to_fit();
         self.seqbuf.shrink_to_fit();
@@ -105,6 +123,12 @@ impl SeqDB{
     }
 }
 
+impl Default for SeqDB{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct SeqDBIterator<'a>{
     seq_db: &'a SeqDB,
     pos: usize,
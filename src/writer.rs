@@ -3,36 +3,48 @@ use std::io;
 use std::io::BufWriter;
 use std::io::Write;
 use ex::fs::File;
+#[cfg(feature = "gzip")]
 use flate2::Compression;
+#[cfg(feature = "gzip")]
 use flate2::write::GzEncoder;
 
-use crate::FileType;
+use crate::{FileType, Error};
 use crate::record::{RefRecord,OwnedRecord,Record};
-use crate::figure_out_file_format;
+use crate::detect_format;
 
 pub trait SeqRecordWriter{
     // We can't use the generic Record trait here because then this can not be made into a trait object
     // so we have separate functions for owned and ref records.
-    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Box<dyn std::error::Error>>;
-    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Box<dyn std::error::Error>>;
+    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Error>;
+    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Error>;
 
-    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    fn flush(&mut self) -> Result<(), Error>;
+}
+
+// Writer options that DynamicFastXWriter needs to reach through the trait object.
+// Implemented for all FastXWriters over the generic parameter W.
+trait JSeqIOWriterInterface: SeqRecordWriter{
+    fn set_crlf(&mut self, crlf: bool);
+
+    // Flushes and writes the end of the compressed stream, if any
+    fn finish(self: Box<Self>) -> Result<(), Error>;
 }
 
 // A dynamic writer, i.e. one that takes no generics and uses dyn instead
 pub struct DynamicFastXWriter {
-    stream: Box<dyn SeqRecordWriter + Send>,
+    stream: Box<dyn JSeqIOWriterInterface + Send>,
 }
 
 // Non-dynamic writer, i.e. a writer that takes the internal stream as a generic parameter
 pub struct FastXWriter<W: Write>{
     pub filetype: FileType,
     pub output: BufWriter<W>,
+    line_end: &'static [u8], // "\n" or "\r\n"
 }
 
 impl DynamicFastXWriter{
 
-    pub fn write<Rec: Record>(&mut self, rec: &Rec) -> Result<(), Box<dyn std::error::Error>>{
+    pub fn write<Rec: Record>(&mut self, rec: &Rec) -> Result<(), Error>{
         let r = RefRecord{head: rec.head(), seq: rec.seq(), qual: rec.qual()};
         self.stream.write_ref_record(&r)?;
         Ok(())
@@ -45,65 +57,208 @@ impl DynamicFastXWriter{
         DynamicFastXWriter {stream: Box::new(writer)}
     }
 
-    // Write to a file
-    pub fn new_to_file<P: AsRef<std::path::Path>>(filename: &P) -> Result<Self, Box<dyn std::error::Error>> {
-        let output = File::create(filename)?;
-        match figure_out_file_format(filename){
-            (FileType::FASTQ, true) =>{
-                let gzencoder = GzEncoder::<File>::new(output, Compression::fast());
-                Ok(Self::new(gzencoder, FileType::FASTQ))
+    // Write to a file. The file type and compression are detected from the file extension.
+    pub fn new_to_file<P: AsRef<std::path::Path>>(filename: &P) -> Result<Self, Error> {
+        WriterBuilder::new().to_file(filename)
+    }
+
+    // Write lines ending in "\r\n" instead of "\n".
+    pub fn set_crlf(&mut self, crlf: bool){
+        self.stream.set_crlf(crlf);
+    }
+
+    // Flushes the output and writes the end of the compressed stream, returning any error.
+    // Dropping the writer does the same, but the errors are lost.
+    pub fn finish(self) -> Result<(), Error>{
+        self.stream.finish()
+    }
+
+    // Panics if the compressor can not be created, e.g. if the compression type is disabled.
+    // Use try_new_to_stdout or WriterBuilder::to_stdout to get an error instead.
+    pub fn new_to_stdout(filetype: FileType, compression_type: crate::CompressionType) -> Self {
+        match Self::try_new_to_stdout(filetype, compression_type){
+            Ok(writer) => writer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new_to_stdout(filetype: FileType, compression_type: crate::CompressionType) -> Result<Self, Error> {
+        WriterBuilder::new().filetype(filetype).compression_type(compression_type).to_stdout()
+    }
+}
+
+// Configures and creates writers. For example:
+//
+// let writer = WriterBuilder::new().compression_level(9).to_file(&"reads.fq.gz")?;
+//
+// When writing to a file, the file type and compression type that are not set are detected from the
+// file extension. For other outputs the file type must be set, and the default is no compression.
+#[derive(Clone, Debug, Default)]
+pub struct WriterBuilder{
+    filetype: Option<FileType>,
+    compression_type: Option<crate::CompressionType>,
+    compression_level: Option<i32>,
+    crlf: bool,
+}
+
+impl WriterBuilder{
+
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn filetype(mut self, filetype: FileType) -> Self{
+        self.filetype = Some(filetype);
+        self
+    }
+
+    pub fn compression_type(mut self, compression_type: crate::CompressionType) -> Self{
+        self.compression_type = Some(compression_type);
+        self
+    }
+
+    // The meaning depends on the compression type: 0-9 for gzip (default 1), 1-22 for zstd (default 3),
+    // 1-9 for bzip2 (default 6) and 0-9 for xz (default 6).
+    // Levels outside of the range are clamped to the range.
+    pub fn compression_level(mut self, compression_level: i32) -> Self{
+        self.compression_level = Some(compression_level);
+        self
+    }
+
+    // Write lines ending in "\r\n" instead of "\n".
+    pub fn crlf(mut self, crlf: bool) -> Self{
+        self.crlf = crlf;
+        self
+    }
+
+    pub fn to_file<P: AsRef<std::path::Path>>(&self, filepath: &P) -> Result<DynamicFastXWriter, Error>{
+        let (filetype, compression_type) = match (self.filetype, self.compression_type){
+            (Some(filetype), Some(compression_type)) => (filetype, compression_type),
+            (filetype, compression_type) => {
+                let detected = detect_format(filepath)?;
+                (filetype.unwrap_or(detected.0), compression_type.unwrap_or(detected.1))
+            }
+        };
+        let output = File::create(filepath)?;
+        self.clone().filetype(filetype).compression_type(compression_type).build(output)
+    }
+
+    pub fn to_stdout(&self) -> Result<DynamicFastXWriter, Error>{
+        self.build(io::stdout())
+    }
+
+    // Creates a DynamicFastXWriter over any stream, compressing the output if needed.
+    // No need to give a buffered writer. Buffering is handled internally.
+    pub fn build<W: Write + 'static + Send>(&self, output: W) -> Result<DynamicFastXWriter, Error>{
+        let filetype = match self.filetype{
+            Some(filetype) => filetype,
+            None => return Err(Error::Format("The file type must be set to create a writer".to_string())),
+        };
+        let mut writer = match self.compression_type.unwrap_or(crate::CompressionType::None){
+            #[cfg(feature = "gzip")]
+            crate::CompressionType::Gzip => {
+                let level = Compression::new(self.level(crate::CompressionType::Gzip) as u32);
+                DynamicFastXWriter::new(GzEncoder::new(output, level), filetype)
             },
-            (FileType::FASTQ, false) => {
-                Ok(Self::new(output, FileType::FASTQ))
+            #[cfg(feature = "zstd")]
+            crate::CompressionType::Zstd => {
+                let encoder = zstd::Encoder::new(output, self.level(crate::CompressionType::Zstd))?;
+                DynamicFastXWriter{stream: Box::new(ZstdFastXWriter{writer: Some(FastXWriter::new(encoder, filetype))})}
             },
-            (FileType::FASTA, true) => {
-                let gzencoder = GzEncoder::<File>::new(output, Compression::fast());
-                Ok(Self::new(gzencoder, FileType::FASTA))
+            #[cfg(feature = "bzip2")]
+            crate::CompressionType::Bzip2 => {
+                let level = bzip2::Compression::new(self.level(crate::CompressionType::Bzip2) as u32);
+                DynamicFastXWriter::new(bzip2::write::BzEncoder::new(output, level), filetype)
             },
-            (FileType::FASTA, false) => {
-                Ok(Self::new(output, FileType::FASTA))
+            #[cfg(feature = "xz")]
+            crate::CompressionType::Xz => {
+                DynamicFastXWriter::new(xz2::write::XzEncoder::new(output, self.level(crate::CompressionType::Xz) as u32), filetype)
             },
-        }
+            crate::CompressionType::None => DynamicFastXWriter::new(output, filetype),
+            #[allow(unreachable_patterns)] // Reachable only if some compression features are disabled
+            disabled => return Err(Error::CompressionDisabled(disabled)),
+        };
+        writer.set_crlf(self.crlf);
+        Ok(writer)
     }
 
-    pub fn new_to_stdout(filetype: FileType, compression_type: crate::CompressionType) -> Self {
-        match compression_type{
-            crate::CompressionType::Gzip => Self::new(GzEncoder::new(io::stdout(), Compression::fast()), filetype),
-            crate::CompressionType::None => Self::new(io::stdout(), filetype),
+    // Creates an AsyncFastXWriter over a tokio stream, compressing the output if needed.
+    // The file type must be set, and the default is no compression.
+    #[cfg(feature = "async")]
+    pub fn build_async<W: tokio::io::AsyncWrite + Unpin + Send + 'static>(&self, output: W) -> Result<crate::async_writer::AsyncFastXWriter, Error>{
+        let filetype = match self.filetype{
+            Some(filetype) => filetype,
+            None => return Err(Error::Format("The file type must be set to create a writer".to_string())),
+        };
+        let compression_type = self.compression_type.unwrap_or(crate::CompressionType::None);
+        let output = crate::async_writer::compress(output, compression_type, self.level(compression_type))?;
+        let mut writer = crate::async_writer::AsyncFastXWriter::new_boxed(output, filetype);
+        writer.set_crlf(self.crlf);
+        Ok(writer)
+    }
+
+    // The compression level set with compression_level clamped to the range of the compression type,
+    // or the default level of the compression type
+    #[allow(dead_code)] // Unused if all compression features are disabled
+    fn level(&self, compression_type: crate::CompressionType) -> i32{
+        let (min, max, default) = match compression_type{
+            #[cfg(feature = "zstd")]
+            crate::CompressionType::Zstd => (1, *zstd::compression_level_range().end(), zstd::DEFAULT_COMPRESSION_LEVEL),
+            crate::CompressionType::Gzip => (0, 9, 1),
+            crate::CompressionType::Bzip2 => (1, 9, 6),
+            crate::CompressionType::Xz => (0, 9, 6),
+            _ => (0, 0, 0), // No compression, or support for it is disabled
+        };
+        self.compression_level.map_or(default, |level| level.clamp(min, max))
+    }
+}
+
+// Writes a record in FASTA or FASTQ format. Shared by the synchronous and asynchronous writers.
+pub(crate) fn write_record<W: Write, Rec: Record>(output: &mut W, filetype: FileType, line_end: &[u8], rec: &Rec) -> Result<(), std::io::Error> {
+    match filetype{
+        FileType::FASTA => {
+            output.write_all(b">")?;
+            output.write_all(rec.head())?;
+            output.write_all(line_end)?;
+            output.write_all(rec.seq())?;
+            output.write_all(line_end)?;
+        }
+        FileType::FASTQ => {
+            output.write_all(b"@")?;
+            output.write_all(rec.head())?;
+            output.write_all(line_end)?;
+            output.write_all(rec.seq())?;
+            output.write_all(line_end)?;
+            output.write_all(b"+")?;
+            output.write_all(line_end)?;
+            match rec.qual(){
+                Some(qual) => output.write_all(qual)?,
+                None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Quality values missing from a record written to FASTQ")),
+            }
+            output.write_all(line_end)?;
         }
     }
+    Ok(())
 }
 
 impl<W: Write> FastXWriter<W>{
     pub fn write<Rec : Record>(&mut self, rec: &Rec) -> Result<(), std::io::Error> {
-        match &self.filetype{
-            FileType::FASTA => {
-                self.output.write_all(b">")?;
-                self.output.write_all(rec.head())?;
-                self.output.write_all(b"\n")?;
-                self.output.write_all(rec.seq())?;
-                self.output.write_all(b"\n")?;
-            }
-            FileType::FASTQ => {
-                self.output.write_all(b"@")?;
-                self.output.write_all(rec.head())?;
-                self.output.write_all(b"\n")?;
-                self.output.write_all(rec.seq())?;
-                self.output.write_all(b"\n+\n")?;
-                self.output.write_all(rec.qual().expect("Quality values missing"))?;
-                self.output.write_all(b"\n")?;
-            }
-        }
-        Ok(())
+        write_record(&mut self.output, self.filetype, self.line_end, rec)
     }
 
     pub fn new(output: W, filetype: FileType) -> Self{
         Self{
             filetype,
-            output: BufWriter::<W>::new(output)
+            output: BufWriter::<W>::new(output),
+            line_end: b"\n",
         }
     }
 
+    // Write lines ending in "\r\n" instead of "\n".
+    pub fn set_crlf(&mut self, crlf: bool){
+        self.line_end = if crlf {b"\r\n"} else {b"\n"};
+    }
+
     pub fn flush(&mut self) -> Result<(), std::io::Error>{
         self.output.flush()?;
         Ok(())
@@ -118,34 +273,98 @@ impl<W: Write> FastXWriter<W>{
 }
 
 impl<W: Write> SeqRecordWriter for FastXWriter<W>{
-    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Box<dyn std::error::Error>>{
+    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Error>{
         self.write(rec)?;
         Ok(())
     }
 
-    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Box<dyn std::error::Error>>{
+    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Error>{
         self.write(rec)?;
         Ok(())
     }
 
-    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>>{
+    fn flush(&mut self) -> Result<(), Error>{
         self.output.flush()?;
         Ok(())
     }
 }
 
+impl<W: Write> JSeqIOWriterInterface for FastXWriter<W>{
+    fn set_crlf(&mut self, crlf: bool){
+        self.set_crlf(crlf);
+    }
+
+    // The gzip, bzip2 and xz encoders write the end of the stream when they are dropped
+    fn finish(mut self: Box<Self>) -> Result<(), Error>{
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+// Writes zstd. Unlike zstd's AutoFinishEncoder, which writes the end of the frame on drop and ignores
+// the errors, this keeps the plain encoder so that finish can return the error. The frame is still
+// ended on drop if finish is not called.
+#[cfg(feature = "zstd")]
+struct ZstdFastXWriter<W: Write>{
+    writer: Option<FastXWriter<zstd::Encoder<'static, W>>>, // None after finish
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> ZstdFastXWriter<W>{
+    fn writer(&mut self) -> &mut FastXWriter<zstd::Encoder<'static, W>>{
+        self.writer.as_mut().unwrap() // Only None after finish, which consumes self
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> SeqRecordWriter for ZstdFastXWriter<W>{
+    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Error>{
+        self.writer().write_ref_record(rec)
+    }
+
+    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Error>{
+        self.writer().write_owned_record(rec)
+    }
+
+    fn flush(&mut self) -> Result<(), Error>{
+        SeqRecordWriter::flush(self.writer())
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> JSeqIOWriterInterface for ZstdFastXWriter<W>{
+    fn set_crlf(&mut self, crlf: bool){
+        self.writer().set_crlf(crlf);
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error>{
+        let writer = self.writer.take().unwrap();
+        writer.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Drop for ZstdFastXWriter<W>{
+    fn drop(&mut self){
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.into_inner().and_then(|encoder| encoder.finish()); // Errors can not be returned from drop
+        }
+    }
+}
+
 impl SeqRecordWriter for DynamicFastXWriter{
-    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Box<dyn std::error::Error>> {
+    fn write_ref_record(&mut self, rec: &RefRecord) -> Result<(), Error> {
         self.stream.write_ref_record(rec)?;
         Ok(())
     }
 
-    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Box<dyn std::error::Error>> {
+    fn write_owned_record(&mut self, rec: &OwnedRecord) -> Result<(), Error> {
         self.stream.write_owned_record(rec)?;
         Ok(())
     }
 
-    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>>{
+    fn flush(&mut self) -> Result<(), Error>{
         self.stream.flush()?;
         Ok(())
     }
@@ -0,0 +1,154 @@
+// Fuzz-style tests that feed arbitrary bytes to the readers and check that they never panic.
+// Malformed input must always come back as an error.
+
+use std::io::{BufReader, Cursor};
+
+use jseqio::reader::*;
+
+// Small deterministic xorshift generator so that failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Parses everything in the data and returns the number of records, or None if there was an error.
+fn parse_all(data: Vec<u8>) -> Option<usize> {
+    parse_all_with_mode(data, false)
+}
+
+fn parse_all_with_mode(data: Vec<u8>, multiline_fastq: bool) -> Option<usize> {
+    let mut reader = DynamicFastXReader::new(BufReader::new(Cursor::new(data))).ok()?;
+    reader.set_multiline_fastq(multiline_fastq);
+    let mut n_records = 0;
+    loop {
+        match reader.read_next() {
+            Ok(Some(_)) => n_records += 1,
+            Ok(None) => return Some(n_records),
+            Err(_) => return None,
+        }
+    }
+}
+
+// Random bytes drawn mostly from the characters that are meaningful to the parsers
+fn random_fastx_like_bytes(rng: &mut Rng) -> Vec<u8> {
+    let alphabet = b">@+\n\nACGTNacgtI!~ \r";
+    let len = rng.below(200);
+    (0..len).map(|_| {
+        if rng.below(10) == 0 {
+            rng.next() as u8 // Any byte
+        } else {
+            alphabet[rng.below(alphabet.len())]
+        }
+    }).collect()
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    for i in 0..5000 {
+        parse_all_with_mode(random_fastx_like_bytes(&mut rng), i % 2 == 0);
+    }
+}
+
+#[test]
+fn mutated_files_never_panic() {
+    let mut rng = Rng(0x9E3779B97F4A7C15);
+    for filename in ["tests/data/reads.fastq", "tests/data/reads.fna", "tests/data/reads.fastq.gz", "tests/data/reads.fasta.gz", "tests/data/reads.fastq.bgz"] {
+        let original = std::fs::read(filename).unwrap();
+        for _ in 0..300 {
+            let mut data = original.clone();
+            for _ in 0..1 + rng.below(4) {
+                match rng.below(3) {
+                    0 => { // Flip a byte
+                        let i = rng.below(data.len());
+                        data[i] = rng.next() as u8;
+                    },
+                    1 => { // Truncate
+                        let len = rng.below(data.len() + 1);
+                        data.truncate(len);
+                    },
+                    _ => { // Insert a byte
+                        let i = rng.below(data.len() + 1);
+                        data.insert(i, rng.next() as u8);
+                    },
+                }
+                if data.is_empty() {
+                    break;
+                }
+            }
+            parse_all(data);
+        }
+    }
+}
+
+#[test]
+fn truncated_inputs() {
+    // A missing final newline is harmless
+    assert_eq!(parse_all(b"@r1\nACGT\n+\nIIII".to_vec()), Some(1));
+    assert_eq!(parse_all(b">r1\nACGT\nAC".to_vec()), Some(1));
+
+    let mut reader = StaticFastXReader::new(b">r1\nACGT\nAC".as_slice()).unwrap();
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"ACGTAC");
+
+    // Truncated records are errors
+    assert_eq!(parse_all(b"@r1".to_vec()), None);
+    assert_eq!(parse_all(b"@r1\nACGT".to_vec()), None);
+    assert_eq!(parse_all(b"@r1\nACGT\n+".to_vec()), None);
+    assert_eq!(parse_all(b">r1".to_vec()), None);
+    assert_eq!(parse_all(b"@".to_vec()), None);
+    assert_eq!(parse_all(b"@r1\nACGT\n+\nIIII\nACGT\n".to_vec()), None);
+}
+
+#[test]
+fn missing_file_is_an_error() {
+    let err = DynamicFastXReader::from_file(&"tests/data/does_not_exist.fna").err().unwrap();
+    assert!(matches!(err, jseqio::Error::Io(_)));
+}
+
+// Reads all records with f, stopping at the first error, which is turned into its message
+#[cfg(feature = "mmap")]
+fn collect_records(mut f: impl FnMut() -> Result<Option<jseqio::record::OwnedRecord>, jseqio::Error>) -> Vec<Result<jseqio::record::OwnedRecord, String>> {
+    let mut records = Vec::new();
+    loop {
+        match f() {
+            Ok(Some(rec)) => records.push(Ok(rec)),
+            Ok(None) => return records,
+            Err(e) => { records.push(Err(e.to_string())); return records },
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_reader_matches_streaming_reader() {
+    let path = "/tmp/jseqio_fuzz_mmap.fx";
+    let mut rng = Rng(0xD1B54A32D192ED03);
+    let mut inputs: Vec<Vec<u8>> = (0..2000).map(|_| random_fastx_like_bytes(&mut rng)).collect();
+    inputs.push(b">r1\nAC\r\ngt\n\n>r2\n>r3\nA".to_vec());
+    inputs.push(b"@r1\nAC\ngt\n+\nII\nII\n@r2\n\n+\n\n".to_vec());
+    for (i, data) in inputs.iter().enumerate() {
+        std::fs::write(path, data).unwrap();
+        let case_policy = [CasePolicy::Upper, CasePolicy::Lower, CasePolicy::Keep][i % 3];
+        let builder = ReaderBuilder::new().filename("fuzz").case_policy(case_policy).multiline_fastq(i % 2 == 0);
+
+        let streamed = match builder.build(Cursor::new(data.clone())) {
+            Ok(mut reader) => collect_records(|| reader.read_next().map(|r| r.map(|r| r.to_owned()))),
+            Err(e) => vec![Err(e.to_string())],
+        };
+        let mapped = match builder.mmap_file(&path) {
+            Ok(mut reader) => collect_records(|| reader.read_next().map(|r| r.map(|r| r.to_owned()))),
+            Err(e) => vec![Err(e.to_string())],
+        };
+        assert_eq!(mapped, streamed, "input {:?}", String::from_utf8_lossy(data));
+    }
+}
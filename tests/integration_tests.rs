@@ -10,6 +10,7 @@ use jseqio::writer::*;
 use jseqio::*;
 
 // Utility function
+#[cfg(feature = "gzip")]
 fn get_test_filenames() -> Vec<&'static str>{
     vec!["tests/data/reads.fastq", "tests/data/reads.fna", "tests/data/reads.fastq.gz", "tests/data/reads.fasta.gz"]
 }
@@ -124,6 +125,7 @@ fn fasta() {
         "GATCGGAAGAGCAcACGTCTGAACNCCAGTCACTTAGGCATCTCGNNNN".to_owned(),
     ];
 
+    #[allow(clippy::ptr_arg)]
     fn split_seq_to_lines(seq: &String, line_length: usize) -> Vec<String> {
         let mut i: usize = 0;
         let mut lines = Vec::<String>::new();
@@ -202,12 +204,13 @@ fn test_figure_out_file_format() {
 }
 
 // Utility function
-fn get_test_reader(data: &[u8]) -> Result<DynamicFastXReader, Box<dyn std::error::Error>>{
+fn get_test_reader(data: &[u8]) -> Result<DynamicFastXReader, jseqio::Error>{
     std::fs::write("/tmp/test.fastq", data).unwrap();
     DynamicFastXReader::from_file(&String::from("/tmp/test.fastq"))
 }
 
 // Utility function
+#[allow(clippy::needless_return)]
 fn get_sequences(filename: &str) -> Vec<Vec<u8>>{
     let reader = DynamicFastXReader::from_file(&String::from(filename)).unwrap();
     let db = reader.into_db().unwrap();
@@ -216,6 +219,7 @@ fn get_sequences(filename: &str) -> Vec<Vec<u8>>{
     return seqs;
 }
 
+#[cfg(feature = "gzip")]
 #[test]
 fn gzip_auto_detection(){
     let true_seqs = get_sequences("tests/data/reads.fastq");
@@ -257,28 +261,28 @@ fn mutable_records() {
 fn fastq_errors() {
     // Not enough quality values
     let reader = get_test_reader("@SRR403017.1 HWUSI-EAS108E_0007:3:1:3797:973/1\nACGT\n+\nIII\n".as_bytes()).unwrap();
-    assert!(reader.into_db().is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.into_db().is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 
     // Too many quality values
     let reader = get_test_reader("@SRR403017.1 HWUSI-EAS108E_0007:3:1:3797:973/1\nACGT\n+\nIIIIIII\n".as_bytes()).unwrap();
-    assert!(reader.into_db().is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.into_db().is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 
     // Not quality line
     let reader = get_test_reader("@SRR403017.1 HWUSI-EAS108E_0007:3:1:3797:973/1\nACGT\n+\n".as_bytes()).unwrap();
-    assert!(reader.into_db().is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.into_db().is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 
     // No plus line
     let reader = get_test_reader("@SRR403017.1 HWUSI-EAS108E_0007:3:1:3797:973/1\nACGT\nIIII\n".as_bytes()).unwrap();
-    assert!(reader.into_db().is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.into_db().is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 
     // No sequence line
     let reader = get_test_reader("@SRR403017.1 HWUSI-EAS108E_0007:3:1:3797:973/1\n+\nIIII\n".as_bytes()).unwrap();
-    assert!(reader.into_db().is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.into_db().is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 
     // No header. Should fail already when creating the reader because it
     // can not detect the file format
     let reader = get_test_reader("\nACGT\n+\nIIII\n".as_bytes());
-    assert!(reader.is_err_and(|e| e.is::<jseqio::reader::ParseError>()));
+    assert!(reader.is_err_and(|e| matches!(e, jseqio::Error::Parse(_))));
 }
 
 // TODO: fasta errors
@@ -310,6 +314,7 @@ fn test_into_db(){
 }
 
 #[test]
+#[allow(clippy::needless_borrow, clippy::map_clone)]
 fn test_into_db_with_rc(){
     let reader = DynamicFastXReader::from_file(&String::from("tests/data/reads.fastq")).unwrap();
     let (fw_db, rc_db) = reader.into_db_with_revcomp().unwrap();
@@ -362,3 +367,1158 @@ fn test_flush_on_drop(){
     assert_eq!(rec.seq, b"ACGT");
     assert!(rec.qual.is_none());
  }
+
+#[cfg(feature = "gzip")]
+#[test]
+fn error_kinds() {
+    // Parse error
+    let reader = DynamicFastXReader::new(BufReader::new("@SRR403017.1\nACGT\n+\nIII\n".as_bytes())).unwrap();
+    let err = reader.into_db().unwrap_err();
+    assert!(matches!(err, jseqio::Error::Parse(_)));
+    assert!(std::error::Error::source(&err).is_some());
+
+    // Truncated gzip stream
+    let mut data = std::fs::read("tests/data/reads.fastq.gz").unwrap();
+    data.truncate(data.len() / 2);
+    let reader = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(data)));
+    let err = match reader {
+        Ok(reader) => reader.into_db().unwrap_err(),
+        Err(e) => e,
+    };
+    assert!(matches!(err, jseqio::Error::Decompression(_)));
+}
+
+#[test]
+fn parse_error_position() {
+    let data = "@r0\nACGT\n+\nIIII\n@r1\nACGT\n+\nIII\n";
+    let mut reader = StaticFastXReader::new(data.as_bytes()).unwrap();
+    reader.filename = Some("reads.fq".to_owned());
+    assert!(reader.read_next().unwrap().is_some());
+    assert_eq!(reader.record_index(), 1);
+    assert_eq!(reader.line_number(), 4);
+    assert_eq!(reader.byte_offset(), 16);
+
+    let err = match reader.read_next() {
+        Err(jseqio::Error::Parse(e)) => e,
+        _ => panic!("Expected a parse error"),
+    };
+    assert_eq!(err.line, 8);
+    assert_eq!(err.byte_offset, 27);
+    assert_eq!(err.record_index, 1);
+    assert_eq!(err.to_string(), "reads.fq:8 (record 1): FASTQ quality line has different length than sequence line (3 vs 4)");
+
+    // FASTA: the sequence of the second record is missing at the end of the file
+    let data = ">r0\nACGT\nAC\n>r1\n";
+    let mut reader = StaticFastXReader::new(data.as_bytes()).unwrap();
+    assert!(reader.read_next().unwrap().is_some());
+    let err = match reader.read_next() {
+        Err(jseqio::Error::Parse(e)) => e,
+        _ => panic!("Expected a parse error"),
+    };
+    assert_eq!(err.line, 4);
+    assert_eq!(err.record_index, 1);
+}
+
+#[test]
+fn reader_buffers() {
+    let mut reader = StaticFastXReader::new(b"@r0\nacgt\n+r0\nIIII\n".as_slice()).unwrap();
+    reader.read_next().unwrap();
+    assert_eq!(reader.head_buf(), b"@r0");
+    assert_eq!(reader.seq_buf(), b"ACGT");
+    assert_eq!(reader.qual_buf(), b"IIII");
+    assert_eq!(reader.plus_buf(), b"+r0\n");
+
+    let mut reader = StaticFastXReader::new(b">r0\nAC\n>r1\nGG\n".as_slice()).unwrap();
+    reader.read_next().unwrap();
+    assert_eq!(reader.head_buf(), b">r0");
+    assert_eq!(reader.fasta_temp_buf(), b">r1\n"); // Read ahead from the next record
+    reader.read_next().unwrap();
+    assert!(reader.fasta_temp_buf().is_empty());
+}
+
+#[test]
+fn crlf_line_endings() {
+    let fastq_data = b"@r0 comment\r\nACGT\r\n+\r\nIIII\r\n@r1\r\nGG\r\n+\r\nII\r\n";
+    let mut reader = DynamicFastXReader::new(BufReader::new(fastq_data.as_slice())).unwrap();
+    assert!(!reader.saw_crlf());
+    let rec = reader.read_next().unwrap().unwrap();
+    assert_eq!(rec.head, b"r0 comment");
+    assert_eq!(rec.seq, b"ACGT");
+    assert_eq!(rec.qual.unwrap(), b"IIII");
+    let rec = reader.read_next().unwrap().unwrap();
+    assert_eq!(rec.seq, b"GG");
+    assert!(reader.read_next().unwrap().is_none());
+    assert!(reader.saw_crlf());
+
+    let fasta_data = b">r0\r\nACGT\r\nAC\r\n>r1\r\nGG";
+    let mut reader = StaticFastXReader::new(fasta_data.as_slice()).unwrap();
+    let rec = reader.read_next().unwrap().unwrap();
+    assert_eq!(rec.head, b"r0");
+    assert_eq!(rec.seq, b"ACGTAC");
+    let rec = reader.read_next().unwrap().unwrap();
+    assert_eq!(rec.head, b"r1");
+    assert_eq!(rec.seq, b"GG");
+    assert!(reader.saw_crlf());
+
+    // Unix line endings are not reported as CRLF
+    let mut reader = StaticFastXReader::new(b">r0\nACGT\n".as_slice()).unwrap();
+    reader.read_next().unwrap();
+    assert!(!reader.saw_crlf());
+
+    // Round trip with CRLF output
+    let mut writer = FastXWriter::<Vec<u8>>::new(Vec::new(), FileType::FASTQ);
+    writer.set_crlf(true);
+    let mut reader = StaticFastXReader::new(fastq_data.as_slice()).unwrap();
+    while let Some(rec) = reader.read_next().unwrap() {
+        writer.write(&rec).unwrap();
+    }
+    assert_eq!(writer.into_inner().unwrap(), fastq_data);
+}
+
+#[test]
+fn case_policy() {
+    let data = b">r0\nACgtnN\nac\n>r1\nttTT\n";
+
+    let mut reader = StaticFastXReader::new(data.as_slice()).unwrap();
+    reader.set_case_policy(CasePolicy::Keep);
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"ACgtnNac");
+
+    let mut reader = StaticFastXReader::new(data.as_slice()).unwrap();
+    reader.set_case_policy(CasePolicy::Lower);
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"acgtnnac");
+
+    // Uppercase is the default
+    let mut reader = StaticFastXReader::new(data.as_slice()).unwrap();
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"ACGTNNAC");
+
+    // The policy also applies when loading into a database
+    let mut reader = DynamicFastXReader::new(BufReader::new(data.as_slice())).unwrap();
+    reader.set_case_policy(CasePolicy::Keep);
+    let (fw_db, rc_db) = reader.into_db_with_revcomp().unwrap();
+    assert_eq!(fw_db.get(1).seq, b"ttTT");
+    assert_eq!(rc_db.get(1).seq, b"AAaa");
+
+    let mut reader = DynamicFastXReader::new(BufReader::new(data.as_slice())).unwrap();
+    reader.set_case_policy(CasePolicy::Keep);
+    assert_eq!(reader.into_db().unwrap().get(0).seq, b"ACgtnNac");
+}
+
+#[test]
+fn multiline_fastq() {
+    // Sequence and quality values wrapped over several lines. The first quality line of r1 starts with '@'.
+    let data = b"@r0\nACGT\nAC\n+\nIIII\nII\n@r1\nGG\nTT\n+r1\n@@@\n@\n@r2\nA\n+\nI\n";
+    let mut reader = StaticFastXReader::new(data.as_slice()).unwrap();
+    reader.set_multiline_fastq(true);
+    let db = reader.into_db().unwrap();
+    assert_eq!(db.sequence_count(), 3);
+    assert_eq!(db.get(0).seq, b"ACGTAC");
+    assert_eq!(db.get(0).qual.unwrap(), b"IIIIII");
+    assert_eq!(db.get(1).head, b"r1");
+    assert_eq!(db.get(1).seq, b"GGTT");
+    assert_eq!(db.get(1).qual.unwrap(), b"@@@@");
+    assert_eq!(db.get(2).seq, b"A");
+
+    // Four-line files parse the same in multi-line mode
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap();
+    reader.set_multiline_fastq(true);
+    let db = reader.into_db().unwrap();
+    let true_db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    assert!(db.iter().eq(true_db.iter()));
+
+    // Multi-line records are rejected in the default mode
+    let reader = StaticFastXReader::new(data.as_slice()).unwrap();
+    assert!(matches!(reader.into_db(), Err(jseqio::Error::Parse(_))));
+
+    // Too many quality values
+    let mut reader = StaticFastXReader::new(b"@r0\nAC\nGT\n+\nII\nIII\n".as_slice()).unwrap();
+    reader.set_multiline_fastq(true);
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Parse(_))));
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format("reads.fa.gz").unwrap(), (FileType::FASTA, CompressionType::Gzip));
+    assert_eq!(detect_format("reads.FQ.GZ").unwrap(), (FileType::FASTQ, CompressionType::Gzip));
+    assert_eq!(detect_format("dir.fq/genome.Fasta").unwrap(), (FileType::FASTA, CompressionType::None));
+    assert_eq!(detect_format("reads.fastq.bgz").unwrap(), (FileType::FASTQ, CompressionType::Gzip));
+    assert!(matches!(detect_format("out.txt"), Err(jseqio::Error::Format(_))));
+    assert!(matches!(detect_format("reads.gz"), Err(jseqio::Error::Format(_))));
+    assert_eq!(detect_format("reads.fq.zst").unwrap(), (FileType::FASTQ, CompressionType::Zstd));
+    assert_eq!(detect_format("reads.FA.ZSTD").unwrap(), (FileType::FASTA, CompressionType::Zstd));
+    assert_eq!(detect_format("reads.fasta.bz2").unwrap(), (FileType::FASTA, CompressionType::Bzip2));
+    assert_eq!(detect_format("reads.fq.XZ").unwrap(), (FileType::FASTQ, CompressionType::Xz));
+    assert!(matches!(detect_format(""), Err(jseqio::Error::Format(_))));
+
+    // Writing to a file with an unknown extension is an error, not a panic
+    assert!(matches!(DynamicFastXWriter::new_to_file(&"/tmp/jseqio_out.txt"), Err(jseqio::Error::Format(_))));
+
+    // Sniffing the contents does not depend on the extension
+    #[cfg(feature = "gzip")]
+    {
+        assert_eq!(sniff_format("tests/data/reads.fastq.gz").unwrap(), (FileType::FASTQ, CompressionType::Gzip));
+        assert_eq!(sniff_format("tests/data/reads.fasta.gz").unwrap(), (FileType::FASTA, CompressionType::Gzip));
+        assert_eq!(sniff_format("tests/data/reads.fastq.bgz").unwrap(), (FileType::FASTQ, CompressionType::Gzip));
+    }
+    assert_eq!(sniff_format("tests/data/reads.fna").unwrap(), (FileType::FASTA, CompressionType::None));
+    std::fs::copy("tests/data/reads.fastq", "/tmp/jseqio_reads.txt").unwrap();
+    assert_eq!(sniff_format("/tmp/jseqio_reads.txt").unwrap(), (FileType::FASTQ, CompressionType::None));
+    assert!(matches!(sniff_format("tests/data/does_not_exist.fq"), Err(jseqio::Error::Io(_))));
+    std::fs::write("/tmp/jseqio_not_fastx.txt", b"ACGT\n").unwrap();
+    match sniff_format("/tmp/jseqio_not_fastx.txt") {
+        Err(jseqio::Error::Parse(e)) => assert_eq!(e.filename.as_deref(), Some("/tmp/jseqio_not_fastx.txt")),
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn reader_builder() {
+    // Same results as the default constructor with a tiny buffer
+    let true_db = DynamicFastXReader::from_file(&"tests/data/reads.fastq.gz").unwrap().into_db().unwrap();
+    let reader = ReaderBuilder::new().buffer_size(3).from_file(&"tests/data/reads.fastq.gz").unwrap();
+    assert_eq!(reader.compression_type(), CompressionType::Gzip);
+    assert_eq!(reader.filetype(), FileType::FASTQ);
+    assert!(reader.into_db().unwrap().iter().eq(true_db.iter()));
+
+    // Buffer sizes that are too small to hold anything are rounded up
+    for buffer_size in [0, 1] {
+        for filename in ["tests/data/reads.fastq", "tests/data/reads.fastq.gz"] {
+            let reader = ReaderBuilder::new().buffer_size(buffer_size).from_file(&filename).unwrap();
+            assert!(reader.into_db().unwrap().iter().eq(true_db.iter()));
+        }
+    }
+
+    // The formats are detected from inputs that give one byte per read, like a slow pipe
+    for filename in ["tests/data/reads.fastq", "tests/data/reads.fastq.gz", "tests/data/reads.fastq.bgz"] {
+        let reader = DynamicFastXReader::new(BufReader::with_capacity(1, File::open(filename).unwrap())).unwrap();
+        assert!(reader.into_db().unwrap().iter().eq(true_db.iter()));
+    }
+
+    // Options are applied to the readers
+    let builder = ReaderBuilder::new().case_policy(CasePolicy::Keep).multiline_fastq(true).filename("reads.fq");
+    let mut reader = builder.build_static(b"@r0\nAc\ngT\n+\nII\nII\n".as_slice()).unwrap();
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"AcgT");
+    let mut reader = builder.build(BufReader::new(b"@r0\nAc\n+\nIII\n".as_slice())).unwrap();
+    match reader.read_next() {
+        Err(jseqio::Error::Parse(e)) => assert_eq!(e.filename.as_deref(), Some("reads.fq")),
+        _ => panic!("Expected a parse error"),
+    }
+
+    // Forced file type: the header check happens when reading
+    let mut reader = ReaderBuilder::new().filetype(FileType::FASTQ).build(BufReader::new(b">r0\nACGT\n".as_slice())).unwrap();
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Parse(_))));
+
+    // Forced compression
+    let data = std::fs::read("tests/data/reads.fasta.gz").unwrap();
+    let reader = ReaderBuilder::new().compression_type(CompressionType::None).build(BufReader::new(std::io::Cursor::new(data.clone())));
+    assert!(reader.is_err());
+    let reader = ReaderBuilder::new().compression_type(CompressionType::Gzip).build(BufReader::new(std::io::Cursor::new(data))).unwrap();
+    assert_eq!(reader.into_db().unwrap().sequence_count(), true_db.sequence_count());
+    assert!(ReaderBuilder::new().compression_type(CompressionType::Gzip).build_static(b">r0\nA\n".as_slice()).is_err());
+
+    // The filename is added to format detection errors
+    std::fs::write("/tmp/jseqio_builder_test.txt", b"hello").unwrap();
+    match ReaderBuilder::new().from_file(&"/tmp/jseqio_builder_test.txt") {
+        Err(jseqio::Error::Parse(e)) => assert_eq!(e.filename.as_deref(), Some("/tmp/jseqio_builder_test.txt")),
+        _ => panic!("Expected a parse error"),
+    }
+}
+
+#[test]
+fn test_mate_name() {
+    use jseqio::paired::mate_name;
+    assert_eq!(mate_name(b"SRR403017.1/1"), b"SRR403017.1");
+    assert_eq!(mate_name(b"SRR403017.1/2 extra"), b"SRR403017.1");
+    assert_eq!(mate_name(b"M00123:8:000:1:1101:15589:1331 1:N:0:ATCACG"), b"M00123:8:000:1:1101:15589:1331");
+    assert_eq!(mate_name(b"read\t2:N:0"), b"read");
+    assert_eq!(mate_name(b"read/3"), b"read/3");
+    assert_eq!(mate_name(b""), b"");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn paired_reader() {
+    use jseqio::paired::*;
+    let r1 = b"@p0/1\nACGT\n+\nIIII\n@p1 1:N:0:ATCACG\nGGGG\n+\nIIII\n";
+    let r2 = b"@p0/2\nTTTT\n+\nIIII\n@p1 2:N:0:ATCACG\nCCCC\n+\nIIII\n";
+
+    let mut reader = PairedFastXReader::new(StaticFastXReader::new(r1.as_slice()).unwrap(), StaticFastXReader::new(r2.as_slice()).unwrap());
+    let (m1, m2) = reader.read_next().unwrap().unwrap();
+    assert_eq!((m1.seq, m2.seq), (b"ACGT".as_slice(), b"TTTT".as_slice()));
+    let (m1, m2) = reader.read_next().unwrap().unwrap();
+    assert_eq!((m1.seq, m2.seq), (b"GGGG".as_slice(), b"CCCC".as_slice()));
+    assert!(reader.read_next().unwrap().is_none());
+    assert_eq!(reader.record_index(), 2);
+
+    // Second file ends early
+    let mut reader = PairedFastXReader::new(StaticFastXReader::new(r1.as_slice()).unwrap(), StaticFastXReader::new(&r2[..18]).unwrap());
+    reader.read_next().unwrap();
+    match reader.read_next() {
+        Err(jseqio::Error::Pair(e)) => {
+            assert_eq!(e.kind, PairErrorKind::SecondEndedEarly);
+            assert_eq!(e.record_index, 1);
+        },
+        _ => panic!("Expected a pair error"),
+    }
+
+    // Names diverge
+    let r2_other = b"@p0/2\nTTTT\n+\nIIII\n@p2/2\nCCCC\n+\nIIII\n";
+    let mut reader = PairedFastXReader::new(StaticFastXReader::new(r1.as_slice()).unwrap(), StaticFastXReader::new(r2_other.as_slice()).unwrap());
+    reader.read_next().unwrap();
+    match reader.read_next() {
+        Err(jseqio::Error::Pair(e)) => {
+            assert_eq!(e.kind, PairErrorKind::NameMismatch{name1: b"p1".to_vec(), name2: b"p2".to_vec()});
+            assert_eq!(e.record_index, 1);
+        },
+        _ => panic!("Expected a pair error"),
+    }
+
+    // Unless the check is turned off
+    let mut reader = PairedFastXReader::new(StaticFastXReader::new(r1.as_slice()).unwrap(), StaticFastXReader::new(r2_other.as_slice()).unwrap());
+    reader.set_check_names(false);
+    assert!(reader.read_next().unwrap().is_some());
+    assert!(reader.read_next().unwrap().is_some());
+
+    // From files. The same file twice is trivially paired.
+    let mut reader = PairedFastXReader::from_files(&"tests/data/reads.fastq", &"tests/data/reads.fastq.gz").unwrap();
+    let mut n_pairs = 0;
+    while let Some((m1, m2)) = reader.read_next().unwrap() {
+        assert_eq!(m1, m2);
+        n_pairs += 1;
+    }
+    assert_eq!(n_pairs, get_sequences("tests/data/reads.fastq").len());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn interleaved_pairs() {
+    use jseqio::paired::*;
+    let data = b"@p0/1\nACGT\n+\nIIII\n@p0/2\nTTTT\n+\nIIII\n@p1/1\nGG\n+\nII\n@p1/2\nCC\n+\nII\n";
+    let mut reader = InterleavedPairReader::new(StaticFastXReader::new(data.as_slice()).unwrap());
+    let (m1, m2) = reader.read_next().unwrap().unwrap();
+    assert_eq!((m1.head, m2.head), (b"p0/1".as_slice(), b"p0/2".as_slice()));
+    assert_eq!(m1.qual.unwrap(), b"IIII");
+    let (m1, m2) = reader.read_next().unwrap().unwrap();
+    assert_eq!((m1.seq, m2.seq), (b"GG".as_slice(), b"CC".as_slice()));
+    assert!(reader.read_next().unwrap().is_none());
+
+    // Odd number of records
+    let mut reader = InterleavedPairReader::new(StaticFastXReader::new(&data[..50]).unwrap());
+    reader.read_next().unwrap();
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Pair(PairError{kind: PairErrorKind::MissingMate, record_index: 1}))));
+
+    // Mates out of order
+    let data = b"@p0/1\nACGT\n+\nIIII\n@p1/1\nGG\n+\nII\n";
+    let mut reader = InterleavedPairReader::new(StaticFastXReader::new(data.as_slice()).unwrap());
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Pair(PairError{kind: PairErrorKind::NameMismatch{..}, record_index: 0}))));
+
+    // Split and merge round trip
+    let n_reads = get_sequences("tests/data/reads.fastq").len() as u64;
+    assert_eq!(merge_paired(&"tests/data/reads.fastq", &"tests/data/reads.fastq.gz", &"/tmp/jseqio_interleaved.fq.gz").unwrap(), n_reads);
+    assert_eq!(split_interleaved(&"/tmp/jseqio_interleaved.fq.gz", &"/tmp/jseqio_split_R1.fq", &"/tmp/jseqio_split_R2.fastq.gz").unwrap(), n_reads);
+    let true_db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    for filename in ["/tmp/jseqio_split_R1.fq", "/tmp/jseqio_split_R2.fastq.gz"] {
+        let db = DynamicFastXReader::from_file(&filename).unwrap().into_db().unwrap();
+        assert!(db.iter().eq(true_db.iter()));
+    }
+
+    // Writing pairs
+    let mut writer = InterleavedFastXWriter::new(DynamicFastXWriter::new_to_file(&"/tmp/jseqio_pairs.fna").unwrap());
+    let m1 = OwnedRecord{head: b"p0/1".to_vec(), seq: b"ACGT".to_vec(), qual: None};
+    let m2 = OwnedRecord{head: b"p0/2".to_vec(), seq: b"TTTT".to_vec(), qual: None};
+    writer.write_pair(&m1, &m2).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+    assert_eq!(std::fs::read("/tmp/jseqio_pairs.fna").unwrap(), b">p0/1\nACGT\n>p0/2\nTTTT\n");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn multi_file_reader() {
+    use jseqio::multi::*;
+    std::fs::write("/tmp/jseqio_multi_empty.fq", b"").unwrap();
+    let n_reads = get_sequences("tests/data/reads.fastq").len() as u64;
+
+    let mut reader = MultiFastXReader::new(&["tests/data/reads.fastq", "/tmp/jseqio_multi_empty.fq", "tests/data/reads.fastq.gz"]);
+    let mut n_records = 0;
+    while let Some(rec) = reader.read_next().unwrap() {
+        assert!(rec.qual.is_some());
+        let (file_index, path) = if n_records < n_reads { (0, "tests/data/reads.fastq") } else { (2, "tests/data/reads.fastq.gz") };
+        assert_eq!(reader.source_file_index(), file_index);
+        assert_eq!(reader.source_path().unwrap(), std::path::Path::new(path));
+        assert_eq!(reader.source_record_index(), n_records % n_reads);
+        n_records += 1;
+    }
+    assert_eq!(n_records, 2 * n_reads);
+    assert_eq!(reader.filetype(), Some(FileType::FASTQ));
+    assert!(reader.read_next().unwrap().is_none());
+
+    // Mixed formats
+    let paths = ["tests/data/reads.fastq", "tests/data/reads.fna"];
+    let mut reader = MultiFastXReader::new(&paths);
+    let err = loop {
+        match reader.read_next() {
+            Ok(Some(_)) => (),
+            Ok(None) => panic!("Expected an error"),
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(err, jseqio::Error::Format(_)));
+    assert_eq!(reader.source_file_index(), 1);
+
+    let mut reader = MultiFastXReader::new(&paths);
+    reader.set_mixed_format_policy(MixedFormatPolicy::Allow);
+    reader.set_reader_builder(ReaderBuilder::new().case_policy(CasePolicy::Keep));
+    let mut db = jseqio::seq_db::SeqDB::new();
+    while let Some(rec) = reader.read_next().unwrap() {
+        db.push_record(rec);
+    }
+    assert_eq!(db.sequence_count() as u64, 2 * n_reads);
+    assert!(db.get(0).qual.is_some());
+    assert!(db.get(n_reads as usize).qual.is_none());
+
+    // Missing file
+    let mut reader = MultiFastXReader::new(&["tests/data/reads.fna", "tests/data/does_not_exist.fna"]);
+    for _ in 0..n_reads {
+        assert!(reader.read_next().unwrap().is_some());
+    }
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Io(_))));
+
+    // A record that fails to parse is not counted in the record index
+    std::fs::write("/tmp/jseqio_multi_bad.fq", b"@r0\nAC\n+\nII\n@r1\nAC\n+\nII\n@r2\nAC\n+\nI\n").unwrap();
+    let mut reader = MultiFastXReader::new(&["/tmp/jseqio_multi_bad.fq"]);
+    reader.read_next().unwrap();
+    reader.read_next().unwrap();
+    assert_eq!(reader.source_record_index(), 1);
+    assert!(matches!(reader.read_next(), Err(jseqio::Error::Parse(_))));
+    assert_eq!(reader.source_record_index(), 1);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn read_batch() {
+    use jseqio::batch::RecordBatch;
+    let seqs = get_sequences("tests/data/reads.fastq");
+
+    // Batches limited by the number of records
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq.gz").unwrap();
+    let mut batch = RecordBatch::new();
+    let mut all = Vec::new();
+    loop {
+        let n = reader.read_batch(&mut batch, 7, usize::MAX).unwrap();
+        assert_eq!(n, batch.len());
+        if n == 0 {
+            break;
+        }
+        assert!(n <= 7);
+        all.extend(batch.iter().map(|rec| rec.to_owned()));
+    }
+    assert_eq!(all.len(), seqs.len());
+    for (rec, seq) in all.iter().zip(seqs.iter()) {
+        assert_eq!(&rec.seq, seq);
+        assert!(rec.qual.is_some());
+    }
+
+    // Batches limited by the number of bytes. Every batch gets at least one record.
+    let mut reader = StaticFastXReader::new(b">a\nACGT\n>b\nACGTACGT\n>c\nA\n".as_slice()).unwrap();
+    assert_eq!(reader.read_batch(&mut batch, 100, 1).unwrap(), 1);
+    assert_eq!(batch.get(0).head, b"a");
+    assert_eq!(batch.byte_size(), 5);
+    assert_eq!(reader.read_batch(&mut batch, 100, 12).unwrap(), 2);
+    assert_eq!(batch.get(1).seq, b"A");
+    assert_eq!(reader.read_batch(&mut batch, 100, 12).unwrap(), 0);
+    assert!(batch.is_empty());
+
+    // Batches can be sent to worker threads and back for reuse
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fna").unwrap();
+    let (to_worker, worker_input) = std::sync::mpsc::channel::<RecordBatch>();
+    let (worker_output, from_worker) = std::sync::mpsc::channel::<(RecordBatch, usize)>();
+    let worker = std::thread::spawn(move || {
+        for batch in worker_input {
+            let total_length = batch.iter().map(|rec| rec.seq.len()).sum();
+            worker_output.send((batch, total_length)).unwrap();
+        }
+    });
+    let mut batch = RecordBatch::new();
+    let mut total_length = 0;
+    while reader.read_batch(&mut batch, 10, 1 << 20).unwrap() > 0 {
+        to_worker.send(batch).unwrap();
+        let (returned, length) = from_worker.recv().unwrap();
+        batch = returned;
+        total_length += length;
+    }
+    drop(to_worker);
+    worker.join().unwrap();
+    assert_eq!(total_length, seqs.iter().map(|s| s.len()).sum::<usize>());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn pipeline() {
+    use jseqio::pipeline::Pipeline;
+    let seqs = get_sequences("tests/data/reads.fastq");
+
+    // Reverse complement the records and drop those starting with A, with tiny batches so that
+    // the workers finish out of order
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq.gz").unwrap();
+    let mut writer = FastXWriter::new(Vec::<u8>::new(), FileType::FASTQ);
+    let n_written = Pipeline::new().n_threads(4).batch_records(3).queue_size(5).run(&mut reader, &mut writer, |input, output| {
+        for rec in input.iter() {
+            if rec.seq[0] != b'A' {
+                let mut rec = rec.to_owned();
+                rec.reverse_complement();
+                output.push_record(rec);
+            }
+        }
+        Ok(())
+    }).unwrap();
+
+    let written = writer.into_inner().unwrap();
+    let db = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(written))).unwrap().into_db().unwrap();
+    assert_eq!(db.sequence_count() as u64, n_written);
+    let all = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    let expected: Vec<Vec<u8>> = all.iter().filter(|rec| rec.seq[0] != b'A').map(|rec| reverse_complement(rec.seq)).collect();
+    let got: Vec<Vec<u8>> = db.iter().map(|rec| rec.seq.to_vec()).collect();
+    assert_eq!(got, expected);
+    assert!(!expected.is_empty() && expected.len() < seqs.len());
+
+    // Errors from a worker are returned
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap();
+    let mut writer = FastXWriter::new(Vec::<u8>::new(), FileType::FASTQ);
+    let result = Pipeline::new().n_threads(3).batch_records(2).run(&mut reader, &mut writer, |input, _| {
+        if input.iter().any(|rec| rec.head == all.get(5).head) {
+            return Err(jseqio::Error::Format("bad record".to_string()));
+        }
+        Ok(())
+    });
+    assert!(matches!(result, Err(jseqio::Error::Format(msg)) if msg == "bad record"));
+
+    // Parse errors from the reader are returned
+    let mut reader = DynamicFastXReader::new(BufReader::new(b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n".as_slice())).unwrap();
+    let mut writer = FastXWriter::new(Vec::<u8>::new(), FileType::FASTQ);
+    let result = Pipeline::new().n_threads(2).batch_records(1).run(&mut reader, &mut writer, |_, _| Ok(()));
+    assert!(matches!(result, Err(jseqio::Error::Parse(_))));
+
+    // Panics in a worker are propagated
+    let result = std::panic::catch_unwind(|| {
+        let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fna").unwrap();
+        let mut writer = FastXWriter::new(Vec::<u8>::new(), FileType::FASTA);
+        Pipeline::new().n_threads(2).batch_records(1).run(&mut reader, &mut writer, |_, _| panic!("worker panic"))
+    });
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "bgzf-parallel")]
+#[test]
+fn bgzf_reader() {
+    use jseqio::bgzf::*;
+    let true_seqs = get_sequences("tests/data/reads.fastq");
+
+    let data = std::fs::read("tests/data/reads.fastq.bgz").unwrap();
+    assert!(is_bgzf(&data));
+    assert!(!is_bgzf(&std::fs::read("tests/data/reads.fastq.gz").unwrap()));
+    assert!(!is_bgzf(&data[..10]));
+
+    for n_threads in [0, 1, 3] {
+        let reader = ReaderBuilder::new().decompression_threads(n_threads).from_file(&"tests/data/reads.fastq.bgz").unwrap();
+        assert_eq!(reader.compression_type(), CompressionType::Gzip);
+        let db = reader.into_db().unwrap();
+        let seqs: Vec<Vec<u8>> = db.iter().map(|rec| rec.seq.to_vec()).collect();
+        assert_eq!(seqs, true_seqs);
+    }
+
+    // The decompressed stream is the same as the original file
+    let mut decompressed = Vec::new();
+    std::io::Read::read_to_end(&mut BgzfReader::new(data.as_slice(), 2), &mut decompressed).unwrap();
+    assert_eq!(decompressed, std::fs::read("tests/data/reads.fastq").unwrap());
+
+    // A truncated file gives the records before the truncation point and then a decompression error
+    let truncated = data[..data.len() / 2].to_vec();
+    let mut reader = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(truncated))).unwrap();
+    let mut n_records = 0;
+    let err = loop {
+        match reader.read_next() {
+            Ok(Some(_)) => n_records += 1,
+            Ok(None) => panic!("Expected an error"),
+            Err(e) => break e,
+        }
+    };
+    assert!(n_records > 0);
+    assert!(matches!(err, jseqio::Error::Decompression(_)));
+
+    // A corrupted CRC is detected
+    let mut corrupted = data.clone();
+    corrupted[data.len() - 28 - 8] ^= 1; // CRC of the last non-empty block. The end-of-file block is 28 bytes.
+    let reader = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(corrupted))).unwrap();
+    assert!(matches!(reader.into_db(), Err(jseqio::Error::Decompression(_))));
+
+    // An uncompressed size larger than the BGZF maximum in the trailer is an error, not a huge allocation
+    let mut huge = Vec::new();
+    for _ in 0..64 {
+        let mut block = bgzip(b"", 1); // Only the empty end-of-file block
+        let len = block.len();
+        block[len - 4..].copy_from_slice(&0xFFFFFFF0_u32.to_le_bytes());
+        huge.extend_from_slice(&block);
+    }
+    let result = ReaderBuilder::new().decompression_threads(4).build(std::io::Cursor::new(huge)).and_then(|reader| reader.into_db());
+    assert!(matches!(result, Err(jseqio::Error::Decompression(_))));
+}
+
+// Compresses data into BGZF blocks of block_size uncompressed bytes, with an end-of-file block
+#[cfg(feature = "gzip")]
+fn bgzip(data: &[u8], block_size: usize) -> Vec<u8> {
+    use std::io::Write;
+    let mut out = Vec::new();
+    for chunk in data.chunks(block_size).chain(std::iter::once(&[][..])) {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(chunk).unwrap();
+        let deflated = encoder.finish().unwrap();
+        let mut crc = flate2::Crc::new();
+        crc.update(chunk);
+        let bsize = (18 + deflated.len() + 8 - 1) as u16;
+        out.extend_from_slice(&[0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0]);
+        out.extend_from_slice(&bsize.to_le_bytes());
+        out.extend_from_slice(&deflated);
+        out.extend_from_slice(&crc.sum().to_le_bytes());
+        out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    }
+    out
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn faidx() {
+    use jseqio::faidx::*;
+    let fasta = b">chr1 desc\nACGTA\nCGTAC\nGT\n>chr2\nAAAA\nCC\n>empty\n>chr3\nacgtn\n";
+    let fai = "chr1\t12\t11\t5\t6\nchr2\t6\t32\t4\t5\nempty\t0\t47\t0\t0\nchr3\t5\t53\t5\t6\n";
+
+    let index = FastaIndex::build(fasta.as_slice()).unwrap();
+    let mut written = Vec::new();
+    index.write(&mut written).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), fai);
+    assert_eq!(FastaIndex::read(fai.as_bytes()).unwrap(), index);
+    assert_eq!(index.get("chr2").unwrap().length, 6);
+    assert!(index.get("chr4").is_none());
+
+    let mut reader = IndexedFastaReader::new(std::io::Cursor::new(fasta.to_vec()), index.clone());
+    fn check<R: std::io::Read + std::io::Seek>(reader: &mut IndexedFastaReader<R>) {
+        assert_eq!(reader.fetch("chr1", 0, 12).unwrap().seq, b"ACGTACGTACGT");
+        let rec = reader.fetch("chr1", 4, 7).unwrap();
+        assert_eq!((rec.head.as_slice(), rec.seq.as_slice()), (b"chr1:5-7".as_slice(), b"ACG".as_slice()));
+        let rec = reader.fetch_region("chr1:5-7").unwrap();
+        assert_eq!((rec.head.as_slice(), rec.seq.as_slice()), (b"chr1:5-7".as_slice(), b"ACG".as_slice()));
+        assert_eq!(reader.fetch_region("chr1:11").unwrap().seq, b"GT");
+        assert_eq!(reader.fetch_region("chr1:1,0-1,1").unwrap().seq, b"CG"); // Commas are ignored
+        assert_eq!(reader.fetch_region("chr2:3-100").unwrap().seq, b"AACC");
+        assert_eq!(reader.fetch_region("chr3").unwrap().seq, b"acgtn");
+        assert!(reader.fetch_region("empty").unwrap().seq.is_empty());
+        let mut buf = b"old".to_vec();
+        reader.fetch_into("chr2", 1, 5, &mut buf).unwrap();
+        assert_eq!(buf, b"AAAC");
+        assert!(matches!(reader.fetch_region("chr4:1-2"), Err(jseqio::Error::Index(_))));
+        assert!(matches!(reader.fetch_region("chr1:0-5"), Err(jseqio::Error::Index(_))));
+        assert!(matches!(reader.fetch_region("chr1:5-4"), Err(jseqio::Error::Index(_))));
+        assert!(matches!(reader.fetch_region("chr1:20-30"), Err(jseqio::Error::Index(_))));
+    }
+    check(&mut reader);
+
+    // Files on disk, with the index built from the file or loaded from the .fai
+    std::fs::write("/tmp/jseqio_faidx.fa", fasta).unwrap();
+    let _ = std::fs::remove_file("/tmp/jseqio_faidx.fa.fai");
+    check(&mut IndexedFastaReader::from_file(&"/tmp/jseqio_faidx.fa").unwrap());
+    FastaIndex::build_from_file(&"/tmp/jseqio_faidx.fa").unwrap().write_to_file(&"/tmp/jseqio_faidx.fa.fai").unwrap();
+    assert_eq!(std::fs::read_to_string("/tmp/jseqio_faidx.fa.fai").unwrap(), fai);
+    check(&mut IndexedFastaReader::from_file(&"/tmp/jseqio_faidx.fa").unwrap());
+
+    // BGZF with small blocks so that the regions span several blocks, with and without a .gzi file
+    std::fs::write("/tmp/jseqio_faidx.fa.gz", bgzip(fasta, 7)).unwrap();
+    let _ = std::fs::remove_file("/tmp/jseqio_faidx.fa.gz.fai");
+    let _ = std::fs::remove_file("/tmp/jseqio_faidx.fa.gz.gzi");
+    assert_eq!(FastaIndex::build_from_file(&"/tmp/jseqio_faidx.fa.gz").unwrap(), index);
+    check(&mut IndexedFastaReader::from_file(&"/tmp/jseqio_faidx.fa.gz").unwrap());
+    let bgzf_data = bgzip(fasta, 5);
+    let gzi = jseqio::bgzf::GziIndex::build(bgzf_data.as_slice()).unwrap();
+    check(&mut IndexedFastaReader::new_bgzf(std::io::Cursor::new(bgzf_data), index.clone(), gzi));
+    let gzi = jseqio::bgzf::GziIndex::build(std::fs::File::open("/tmp/jseqio_faidx.fa.gz").unwrap()).unwrap();
+    assert_eq!(gzi.locate(0), (0, 0));
+    assert_eq!(gzi.locate(15).1, 14);
+    gzi.write_to_file(&"/tmp/jseqio_faidx.fa.gz.gzi").unwrap();
+    assert_eq!(jseqio::bgzf::GziIndex::from_file(&"/tmp/jseqio_faidx.fa.gz.gzi").unwrap(), gzi);
+    check(&mut IndexedFastaReader::from_file(&"/tmp/jseqio_faidx.fa.gz").unwrap());
+
+    // Plain gzip can not be indexed
+    assert!(matches!(IndexedFastaReader::from_file(&"tests/data/reads.fasta.gz"), Err(jseqio::Error::Format(_))));
+
+    // All lines except the last must have the same length
+    assert!(matches!(FastaIndex::build(b">a\nACG\nACGT\n".as_slice()), Err(jseqio::Error::Index(_))));
+    assert!(matches!(FastaIndex::build(b">a\nACG\nA\nACG\n".as_slice()), Err(jseqio::Error::Index(_))));
+    assert!(matches!(FastaIndex::build(b">a\nA\n>a\nC\n".as_slice()), Err(jseqio::Error::Index(_))));
+    assert!(matches!(FastaIndex::read(b"a\t1\t2\n".as_slice()), Err(jseqio::Error::Index(_))));
+    // Impossible line layouts are rejected when the index is loaded
+    assert!(matches!(FastaIndex::read(b"a\t5\t3\t0\t1\n".as_slice()), Err(jseqio::Error::Index(_))));
+    assert!(matches!(FastaIndex::read(b"a\t5\t3\t5\t4\n".as_slice()), Err(jseqio::Error::Index(_))));
+    assert!(FastaIndex::read(b"empty\t0\t3\t0\t0\n".as_slice()).is_ok());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn fqidx() {
+    use jseqio::fqidx::*;
+    let fastq = b"@r1 comment\nACGT\n+\nIIII\n@r2\nACG\nTA\n+r2\nABC\nDE\n@r3\n\n+\n\n";
+    let fai = "r1\t4\t12\t4\t5\t19\nr2\t5\t28\t3\t4\t39\nr3\t0\t50\t0\t1\t53\n";
+
+    let index = FastqIndex::build(fastq.as_slice()).unwrap();
+    let mut written = Vec::new();
+    index.write(&mut written).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), fai);
+    assert_eq!(FastqIndex::read(fai.as_bytes()).unwrap(), index);
+
+    fn check<R: std::io::Read + std::io::Seek>(reader: &mut IndexedFastqReader<R>) {
+        let rec = reader.fetch("r2").unwrap();
+        assert_eq!(rec, OwnedRecord{head: b"r2".to_vec(), seq: b"ACGTA".to_vec(), qual: Some(b"ABCDE".to_vec())});
+        let rec = reader.fetch("r1").unwrap();
+        assert_eq!(rec, OwnedRecord{head: b"r1".to_vec(), seq: b"ACGT".to_vec(), qual: Some(b"IIII".to_vec())});
+        assert!(reader.fetch("r3").unwrap().seq.is_empty());
+        assert!(matches!(reader.fetch("r4"), Err(jseqio::Error::Index(_))));
+    }
+    check(&mut IndexedFastqReader::new(std::io::Cursor::new(fastq.to_vec()), index.clone()));
+    let bgzf_data = bgzip(fastq, 6);
+    let gzi = jseqio::bgzf::GziIndex::build(bgzf_data.as_slice()).unwrap();
+    check(&mut IndexedFastqReader::new_bgzf(std::io::Cursor::new(bgzf_data), index.clone(), gzi));
+
+    // Every read of a file can be looked up by name. The case of the bases is kept.
+    let db = ReaderBuilder::new().case_policy(CasePolicy::Keep).from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    std::fs::write("/tmp/jseqio_fqidx.fq.gz", bgzip(&std::fs::read("tests/data/reads.fastq").unwrap(), 50)).unwrap();
+    for path in ["tests/data/reads.fastq", "/tmp/jseqio_fqidx.fq.gz"] {
+        let mut reader = IndexedFastqReader::from_file(&path).unwrap();
+        for rec in db.iter().collect::<Vec<_>>().into_iter().rev() {
+            let name = std::str::from_utf8(rec.head).unwrap().split_whitespace().next().unwrap();
+            let fetched = reader.fetch(name).unwrap();
+            assert_eq!(fetched.head, name.as_bytes());
+            assert_eq!(fetched.seq, rec.seq);
+            assert_eq!(fetched.qual.as_deref(), rec.qual);
+        }
+    }
+
+    // Malformed input
+    for bad in [b"@r1\nACGT\n+\nIIIII\n".as_slice(), b"@r1\nACGT\n+\nII\n", b"@r1\nAC\nACG\n+\nIIIII\n", b"@r1\nACG\nAC\n+\nII\nIII\n", b"r1\nA\n+\nI\n", b"@r1\nA\n+\nI\n@r1\nA\n+\nI\n"] {
+        assert!(matches!(FastqIndex::build(bad), Err(jseqio::Error::Index(_))), "{}", String::from_utf8_lossy(bad));
+    }
+    assert!(matches!(FastqIndex::read(b"r1\t1\t2\t0\t1\t5\n".as_slice()), Err(jseqio::Error::Index(_))));
+    let entry = FqiEntry{name: "r1".to_string(), length: 5, offset: 4, line_bases: 0, line_width: 0, qual_offset: 12};
+    assert!(matches!(FastqIndex::new(vec![entry]), Err(jseqio::Error::Index(_))));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_compression() {
+    let db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    let write_all = |writer: &mut DynamicFastXWriter| {
+        for rec in db.iter() {
+            writer.write(&rec).unwrap();
+        }
+        writer.flush().unwrap();
+    };
+
+    // The compression type is chosen by the extension and detected from the magic bytes
+    let mut writer = DynamicFastXWriter::new_to_file(&"/tmp/jseqio_zstd.fq.zst").unwrap();
+    write_all(&mut writer);
+    drop(writer);
+    let data = std::fs::read("/tmp/jseqio_zstd.fq.zst").unwrap();
+    assert_eq!(&data[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+    assert_eq!(sniff_format("/tmp/jseqio_zstd.fq.zst").unwrap(), (FileType::FASTQ, CompressionType::Zstd));
+    let reader = DynamicFastXReader::from_file(&"/tmp/jseqio_zstd.fq.zst").unwrap();
+    assert_eq!(reader.compression_type(), CompressionType::Zstd);
+    let db2 = reader.into_db().unwrap();
+    assert!(db.iter().eq(db2.iter()));
+
+    // The magic bytes are detected even if the input gives fewer bytes per read
+    let reader = DynamicFastXReader::new(BufReader::with_capacity(3, std::io::Cursor::new(data.clone()))).unwrap();
+    assert_eq!(reader.compression_type(), CompressionType::Zstd);
+    assert!(db.iter().eq(reader.into_db().unwrap().iter()));
+
+    // Compression level, file type and compression type set on the builder
+    let mut writer = WriterBuilder::new().compression_level(19).filetype(FileType::FASTA).compression_type(CompressionType::Zstd)
+        .to_file(&"/tmp/jseqio_zstd_level.out").unwrap();
+    write_all(&mut writer);
+    writer.finish().unwrap();
+    let db2 = DynamicFastXReader::from_file(&"/tmp/jseqio_zstd_level.out").unwrap().into_db().unwrap();
+    assert!(db.iter().map(|r| r.seq).eq(db2.iter().map(|r| r.seq)));
+    assert!(db2.iter().all(|r| r.qual.is_none()));
+
+    // Concatenated frames are read as one stream
+    let mut concatenated = data.clone();
+    concatenated.extend_from_slice(&data);
+    let db2 = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(concatenated))).unwrap().into_db().unwrap();
+    assert_eq!(db2.sequence_count(), 2 * db.sequence_count());
+
+    // A truncated stream is a decompression error
+    let truncated = data[..data.len() - 10].to_vec();
+    let result = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(truncated))).and_then(|reader| reader.into_db());
+    assert!(matches!(result, Err(jseqio::Error::Decompression(_))));
+
+    // The builder needs the file type when it can not be detected
+    assert!(matches!(WriterBuilder::new().build(Vec::new()), Err(jseqio::Error::Format(_))));
+
+    // An error in writing the end of the frame is returned from finish
+    struct FailingOutput;
+    impl std::io::Write for FailingOutput {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> { Err(std::io::Error::other("disk full")) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+    let mut writer = WriterBuilder::new().filetype(FileType::FASTQ).compression_type(CompressionType::Zstd).build(FailingOutput).unwrap();
+    writer.write(&db.get(0)).unwrap(); // Buffered
+    assert!(matches!(writer.finish(), Err(jseqio::Error::Io(_))));
+}
+
+#[cfg(all(feature = "bzip2", feature = "xz"))]
+#[test]
+fn bzip2_and_xz_compression() {
+    let db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+    for (path, compression_type, magic) in [("/tmp/jseqio_reads.fq.bz2", CompressionType::Bzip2, b"BZh".as_slice()), ("/tmp/jseqio_reads.fq.xz", CompressionType::Xz, &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00])] {
+        let mut writer = DynamicFastXWriter::new_to_file(&path).unwrap();
+        for rec in db.iter() {
+            writer.write(&rec).unwrap();
+        }
+        drop(writer);
+
+        let data = std::fs::read(path).unwrap();
+        assert!(data.starts_with(magic));
+        let reader = DynamicFastXReader::from_file(&path).unwrap();
+        assert_eq!(reader.compression_type(), compression_type);
+        assert!(db.iter().eq(reader.into_db().unwrap().iter()));
+        let reader = DynamicFastXReader::new(BufReader::with_capacity(5, std::io::Cursor::new(data.clone()))).unwrap();
+        assert_eq!(reader.compression_type(), compression_type);
+        assert!(db.iter().eq(reader.into_db().unwrap().iter()));
+
+        // Concatenated streams are read as one, like with gzip
+        let mut concatenated = data.clone();
+        concatenated.extend_from_slice(&data);
+        let db2 = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(concatenated))).unwrap().into_db().unwrap();
+        assert_eq!(db2.sequence_count(), 2 * db.sequence_count());
+
+        let truncated = data[..data.len() - 10].to_vec();
+        let result = DynamicFastXReader::new(BufReader::new(std::io::Cursor::new(truncated))).and_then(|reader| reader.into_db());
+        assert!(matches!(result, Err(jseqio::Error::Decompression(_))), "{:?}", compression_type);
+
+        // Compression level
+        let mut writer = WriterBuilder::new().compression_level(1).to_file(&path).unwrap();
+        writer.write(&db.get(0)).unwrap();
+        drop(writer);
+        assert_eq!(DynamicFastXReader::from_file(&path).unwrap().into_db().unwrap().sequence_count(), 1);
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn compression_disabled() {
+    let disabled = |r: Result<_, jseqio::Error>| matches!(r, Err(jseqio::Error::CompressionDisabled(CompressionType::Gzip)));
+    assert!(disabled(DynamicFastXReader::from_file(&"tests/data/reads.fastq.gz").map(|_| ())));
+    assert!(disabled(DynamicFastXReader::new(std::io::Cursor::new(std::fs::read("tests/data/reads.fastq.gz").unwrap())).map(|_| ())));
+    assert!(disabled(DynamicFastXWriter::new_to_file(&"/tmp/jseqio_disabled.fq.gz").map(|_| ())));
+    assert!(disabled(jseqio::faidx::IndexedFastaReader::from_file(&"tests/data/reads.fasta.gz").map(|_| ())));
+
+    // Uncompressed files can still be read
+    assert_eq!(DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap().sequence_count(), 10);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_reader() {
+    use jseqio::mmap::MmapFastXReader;
+
+    for filename in ["tests/data/reads.fastq", "tests/data/reads.fna"] {
+        let true_db = DynamicFastXReader::from_file(&filename).unwrap().into_db().unwrap();
+        let mut reader = MmapFastXReader::from_file(&filename).unwrap();
+        let mut n_records = 0;
+        while let Some(rec) = reader.read_next().unwrap() {
+            assert_eq!(rec, true_db.get(n_records));
+            n_records += 1;
+        }
+        assert_eq!(n_records, true_db.sequence_count());
+        assert!(reader.is_at_end());
+        assert_eq!(reader.byte_offset(), std::fs::metadata(filename).unwrap().len());
+        assert_eq!(MmapFastXReader::from_file(&filename).unwrap().into_db().unwrap().sequence_count(), n_records);
+    }
+
+    // Multi-line FASTA sequences and case conversion are copied, the rest is read from the mapped file
+    std::fs::write("/tmp/jseqio_mmap.fa", b">r1 first\r\nACGT\r\nac\r\n>r2\nacgt\n>r3\nTTT").unwrap();
+    let mut reader = ReaderBuilder::new().case_policy(CasePolicy::Keep).mmap_file(&"/tmp/jseqio_mmap.fa").unwrap();
+    assert_eq!(reader.filetype(), FileType::FASTA);
+    let rec = reader.read_next().unwrap().unwrap();
+    assert_eq!((rec.head, rec.seq, rec.qual), (b"r1 first".as_slice(), b"ACGTac".as_slice(), None));
+    assert!(reader.saw_crlf());
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"acgt");
+    assert_eq!((reader.record_index(), reader.line_number()), (2, 6)); // The header of r3 has been read
+    assert_eq!(reader.read_next().unwrap().unwrap().seq, b"TTT");
+    assert!(reader.read_next().unwrap().is_none());
+    let db = MmapFastXReader::from_file(&"/tmp/jseqio_mmap.fa").unwrap().into_db().unwrap();
+    assert_eq!(db.get(1).seq, b"ACGT");
+
+    // Batches work like with the streaming readers
+    let mut reader = MmapFastXReader::from_file(&"tests/data/reads.fastq").unwrap();
+    let mut batch = jseqio::batch::RecordBatch::new();
+    assert_eq!(reader.read_batch(&mut batch, 4, usize::MAX).unwrap(), 4);
+    assert_eq!(batch.get(0).qual.unwrap().len(), batch.get(0).seq.len());
+
+    // Errors have the same positions as in the streaming reader
+    std::fs::write("/tmp/jseqio_mmap_bad.fq", b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nIII\n").unwrap();
+    let mut reader = MmapFastXReader::from_file(&"/tmp/jseqio_mmap_bad.fq").unwrap();
+    reader.read_next().unwrap();
+    match reader.read_next() {
+        Err(jseqio::Error::Parse(e)) => {
+            assert_eq!((e.line, e.byte_offset, e.record_index), (8, 27, 1));
+            assert_eq!(e.filename.as_deref(), Some("/tmp/jseqio_mmap_bad.fq"));
+        },
+        other => panic!("Expected a parse error, got {:?}", other.map(|r| r.map(|r| r.to_owned()))),
+    }
+
+    // Compressed files can not be mapped
+    #[cfg(feature = "gzip")]
+    assert!(matches!(MmapFastXReader::from_file(&"tests/data/reads.fastq.gz"), Err(jseqio::Error::Format(_))));
+    assert!(matches!(MmapFastXReader::from_file(&"tests/data/does_not_exist.fq"), Err(jseqio::Error::Io(_))));
+    std::fs::write("/tmp/jseqio_mmap_empty.fa", b"").unwrap();
+    assert!(MmapFastXReader::from_file(&"/tmp/jseqio_mmap_empty.fa").unwrap().read_next().unwrap().is_none());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_reader() {
+    use jseqio::async_reader::AsyncFastXReader;
+
+    let mut filenames = vec!["tests/data/reads.fastq", "tests/data/reads.fna"];
+    if cfg!(feature = "gzip") {
+        filenames.extend(["tests/data/reads.fastq.gz", "tests/data/reads.fasta.gz", "tests/data/reads.fastq.bgz"]);
+    }
+    for filename in filenames {
+        let true_reader = DynamicFastXReader::from_file(&filename).unwrap();
+        let compression_type = true_reader.compression_type();
+        let true_db = true_reader.into_db().unwrap();
+
+        let input = tokio::io::BufReader::new(tokio::fs::File::open(filename).await.unwrap());
+        let mut reader = AsyncFastXReader::new(input).await.unwrap();
+        assert_eq!(reader.compression_type(), compression_type);
+        let mut n_records = 0;
+        while let Some(rec) = reader.read_next().await.unwrap() {
+            assert_eq!(rec, true_db.get(n_records));
+            n_records += 1;
+        }
+        assert_eq!(n_records, true_db.sequence_count());
+        assert!(reader.is_at_end().await.unwrap());
+
+        // The compression is detected even if the input gives one byte per read
+        let input = tokio::io::BufReader::with_capacity(1, tokio::fs::File::open(filename).await.unwrap());
+        let reader = AsyncFastXReader::new(input).await.unwrap();
+        assert_eq!(reader.compression_type(), compression_type);
+        assert!(reader.into_db().await.unwrap().iter().eq(true_db.iter()));
+    }
+
+    // The options of the builder are used, and the reader can be moved to another task
+    let data = b">r1\nacgt\n>r2\nAC\r\ngt\r\n".to_vec();
+    let task = tokio::spawn(async move {
+        let mut reader = ReaderBuilder::new().case_policy(CasePolicy::Keep).build_async(std::io::Cursor::new(data)).await.unwrap();
+        assert_eq!(reader.filetype(), FileType::FASTA);
+        assert!(!reader.is_at_end().await.unwrap());
+        assert_eq!(reader.read_next().await.unwrap().unwrap().seq, b"acgt");
+        let rec = reader.read_next_mut().await.unwrap().unwrap();
+        assert_eq!((&*rec.head, &*rec.seq), (b"r2".as_slice(), b"ACgt".as_slice()));
+        assert!(reader.saw_crlf());
+        assert!(reader.read_next().await.unwrap().is_none());
+    });
+    task.await.unwrap();
+
+    // Parse errors are the same as in the synchronous reader
+    for data in [b"@r1\nACGT\n+\nIII\n".as_slice(), b">r1\nACGT\n>r2\n", b"x", b"@r1\nACGT\nIIII\n"] {
+        let builder = ReaderBuilder::new().filename("reads");
+        let sync_err = builder.build(std::io::Cursor::new(data.to_vec())).and_then(|r| r.into_db()).err().unwrap();
+        let async_err = match builder.build_async(std::io::Cursor::new(data.to_vec())).await {
+            Ok(reader) => reader.into_db().await.err().unwrap(),
+            Err(e) => e,
+        };
+        assert_eq!(async_err.to_string(), sync_err.to_string());
+    }
+
+    // Corrupt compressed data is a decompression error
+    #[cfg(feature = "gzip")]
+    {
+        let mut data = std::fs::read("tests/data/reads.fastq.gz").unwrap();
+        data.truncate(data.len() / 2);
+        let result = match AsyncFastXReader::new(std::io::Cursor::new(data)).await {
+            Ok(reader) => reader.into_db().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        assert!(matches!(result, Err(jseqio::Error::Decompression(_))));
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_writer() {
+    use jseqio::async_writer::AsyncFastXWriter;
+
+    let db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+
+    // Uncompressed output is identical to the output of the synchronous writer
+    for (filetype, crlf) in [(FileType::FASTQ, false), (FileType::FASTA, true)] {
+        let mut sync_writer = WriterBuilder::new().filetype(filetype).compression_type(CompressionType::None).crlf(crlf).to_file(&"/tmp/jseqio_sync_out.fx").unwrap();
+        let output = tokio::fs::File::create("/tmp/jseqio_async_out.fx").await.unwrap();
+        let mut async_writer = WriterBuilder::new().filetype(filetype).crlf(crlf).build_async(output).unwrap();
+        for rec in db.iter() {
+            sync_writer.write(&rec).unwrap();
+            async_writer.write(&rec).await.unwrap();
+        }
+        sync_writer.flush().unwrap();
+        async_writer.finish().await.unwrap();
+        assert_eq!(std::fs::read("/tmp/jseqio_async_out.fx").unwrap(), std::fs::read("/tmp/jseqio_sync_out.fx").unwrap());
+    }
+
+    // Compressed output can be read back. The writer can be moved to another task.
+    let mut compression_types = Vec::new();
+    if cfg!(feature = "gzip") { compression_types.push(CompressionType::Gzip) }
+    if cfg!(feature = "zstd") { compression_types.push(CompressionType::Zstd) }
+    if cfg!(feature = "bzip2") { compression_types.push(CompressionType::Bzip2) }
+    if cfg!(feature = "xz") { compression_types.push(CompressionType::Xz) }
+    for compression_type in compression_types {
+        let records: Vec<OwnedRecord> = db.iter().map(|rec| rec.to_owned()).collect();
+        let task = tokio::spawn(async move {
+            let output = tokio::fs::File::create("/tmp/jseqio_async_out.fq.compressed").await.unwrap();
+            let mut writer = WriterBuilder::new().filetype(FileType::FASTQ).compression_type(compression_type).compression_level(100).build_async(output).unwrap();
+            for rec in records.iter() {
+                writer.write(rec).await.unwrap();
+            }
+            writer.finish().await.unwrap();
+        });
+        task.await.unwrap();
+        let reader = DynamicFastXReader::from_file(&"/tmp/jseqio_async_out.fq.compressed").unwrap();
+        assert_eq!(reader.compression_type(), compression_type);
+        let db2 = reader.into_db().unwrap();
+        assert_eq!(db2.sequence_count(), db.sequence_count());
+        assert!(db.iter().zip(db2.iter()).all(|(a, b)| a == b));
+    }
+
+    // A FASTA record can not be written to FASTQ, and nothing of it is written
+    let output = tokio::fs::File::create("/tmp/jseqio_async_out.fq").await.unwrap();
+    let mut writer = AsyncFastXWriter::new(output, FileType::FASTQ);
+    let fasta_rec = OwnedRecord{head: b"r1".to_vec(), seq: b"ACGT".to_vec(), qual: None};
+    assert!(matches!(writer.write(&fasta_rec).await, Err(jseqio::Error::Io(_))));
+    writer.write(&db.get(0)).await.unwrap();
+    writer.finish().await.unwrap();
+    assert_eq!(DynamicFastXReader::from_file(&"/tmp/jseqio_async_out.fq").unwrap().into_db().unwrap().get(0), db.get(0));
+    assert!(matches!(WriterBuilder::new().build_async(Vec::new()), Err(jseqio::Error::Format(_))));
+}
+
+#[test]
+fn for_each_and_owned_iter() {
+    let db = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap();
+
+    let mut reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap();
+    let mut total_length = 0;
+    reader.for_each(|rec| total_length += rec.seq.len()).unwrap();
+    assert_eq!(total_length, db.iter().map(|rec| rec.seq.len()).sum::<usize>());
+
+    // try_for_each stops at the first error of the closure, and works with any error type that can hold a jseqio::Error
+    let mut reader = StaticFastXReader::new(BufReader::new(File::open("tests/data/reads.fastq").unwrap())).unwrap();
+    let mut n_seen = 0;
+    let result: Result<(), Box<dyn std::error::Error>> = reader.try_for_each(|_| {
+        n_seen += 1;
+        if n_seen == 3 { Err("stop".into()) } else { Ok(()) }
+    });
+    assert_eq!(result.unwrap_err().to_string(), "stop");
+    assert_eq!(reader.record_index(), 3);
+    let result: Result<(), jseqio::Error> = StaticFastXReader::new(b"@r1\nACGT\n+\nII\n".as_slice()).unwrap().try_for_each(|_| Ok(()));
+    assert!(matches!(result, Err(jseqio::Error::Parse(_))));
+
+    // The owned records plug into iterator chains
+    let reader = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap();
+    let records: Vec<OwnedRecord> = reader.into_owned_iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(records.len(), db.sequence_count());
+    assert!(records.iter().zip(db.iter()).all(|(a, b)| a.as_ref_record() == b));
+    let reader = StaticFastXReader::new(b">r1\nAC\n>r2\nACGT\n>r3\nA\n".as_slice()).unwrap();
+    let long_names: Vec<Vec<u8>> = reader.into_owned_iter().filter_map(Result::ok).filter(|rec| rec.seq.len() > 1).map(|rec| rec.head).collect();
+    assert_eq!(long_names, vec![b"r1".to_vec(), b"r2".to_vec()]);
+
+    // The iterator ends after an error
+    let mut iter = StaticFastXReader::new(b">r1\nAC\nx\n>r2\n".as_slice()).unwrap().into_owned_iter();
+    assert!(iter.next().unwrap().is_ok());
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn stream_adapters() {
+    use jseqio::adapters::SeqStreamExt;
+
+    let input = b">r1\nacgt\n>r2\nA\n>r3\nccc\n>r4\nGG\n>r5\nttttt\n>r6\nAC\n>r7\nG\n".as_slice();
+    let new_reader = || ReaderBuilder::new().case_policy(CasePolicy::Keep).build_static(input).unwrap();
+    let names = |db: &jseqio::seq_db::SeqDB| db.iter().map(|rec| String::from_utf8(rec.head.to_vec()).unwrap()).collect::<Vec<String>>();
+
+    assert_eq!(names(&new_reader().take(2).collect_db().unwrap()), ["r1", "r2"]);
+    assert_eq!(names(&new_reader().skip(5).collect_db().unwrap()), ["r6", "r7"]);
+    assert_eq!(names(&new_reader().skip(10).collect_db().unwrap()), Vec::<String>::new());
+    assert_eq!(names(&new_reader().step_by(3).collect_db().unwrap()), ["r1", "r4", "r7"]);
+    assert_eq!(names(&new_reader().filter(|rec| rec.seq.len() > 1).collect_db().unwrap()), ["r1", "r3", "r4", "r5", "r6"]);
+
+    // Chained adapters, with map_mut after filter modifying the copy made by filter
+    let mut n_inspected = 0;
+    let db = new_reader()
+        .skip(1)
+        .inspect(|_| n_inspected += 1)
+        .filter(|rec| rec.seq.len() > 1)
+        .map_mut(|rec| rec.seq.make_ascii_uppercase())
+        .take(3)
+        .collect_db().unwrap();
+    assert_eq!(names(&db), ["r3", "r4", "r5"]);
+    assert_eq!(db.iter().map(|rec| rec.seq.to_vec()).collect::<Vec<Vec<u8>>>(), [b"CCC".to_vec(), b"GG".to_vec(), b"TTTTT".to_vec()]);
+    assert_eq!(n_inspected, 4); // take stops reading after the third kept record
+
+    // The adapters work with the writers, and errors from the stream pass through
+    let mut writer = FastXWriter::new(Vec::<u8>::new(), FileType::FASTA);
+    new_reader().map_mut(|rec| rec.seq.make_ascii_lowercase()).step_by(2).try_for_each(|rec| writer.write_ref_record(&rec)).unwrap();
+    assert_eq!(writer.into_inner().unwrap(), b">r1\nacgt\n>r3\nccc\n>r5\nttttt\n>r7\ng\n");
+    let mut stream = StaticFastXReader::new(b"@r1\nAC\n+\nII\n@r2\nACGT\n+\nI\n".as_slice()).unwrap().skip(1);
+    assert!(stream.read_next().is_err());
+}
+
+#[test]
+fn sampling() {
+    use jseqio::adapters::SeqStreamExt;
+    use jseqio::sample::*;
+
+    let mut input = Vec::<u8>::new();
+    for i in 0..1000 {
+        input.extend_from_slice(format!("@r{}\nACGT\n+\nIIII\n", i).as_bytes());
+    }
+    let new_reader = || StaticFastXReader::new(input.as_slice()).unwrap();
+    let indices = |db: &jseqio::seq_db::SeqDB| db.iter().map(|rec| std::str::from_utf8(&rec.head[1..]).unwrap().parse::<usize>().unwrap()).collect::<Vec<usize>>();
+
+    // The same seed gives the same sample, and the records are in the input order
+    let a = indices(&sample_fraction(new_reader(), 0.1, 42).collect_db().unwrap());
+    let b = indices(&sample_fraction(new_reader(), 0.1, 42).collect_db().unwrap());
+    let c = indices(&sample_fraction(new_reader(), 0.1, 43).collect_db().unwrap());
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+    assert!(a.windows(2).all(|w| w[0] < w[1]));
+    assert!(a.len() > 50 && a.len() < 150);
+    assert_eq!(sample_fraction(new_reader(), 0.0, 1).collect_db().unwrap().sequence_count(), 0);
+    assert_eq!(sample_fraction(new_reader(), 1.0, 1).collect_db().unwrap().sequence_count(), 1000);
+
+    let a = indices(&reservoir_sample(new_reader(), 100, 7).unwrap());
+    assert_eq!(a.len(), 100);
+    assert!(a.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(a, indices(&reservoir_sample(new_reader(), 100, 7).unwrap()));
+    assert_ne!(a, indices(&reservoir_sample(new_reader(), 100, 8).unwrap()));
+    assert_eq!(indices(&reservoir_sample(new_reader(), 2000, 7).unwrap()), (0..1000).collect::<Vec<usize>>());
+    assert!(a.iter().any(|&i| i >= 500)); // Not only the first records
+
+    let a = indices(&sample_exact(std::io::Cursor::new(&input), 100, 7).unwrap());
+    assert_eq!(a.len(), 100);
+    assert!(a.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(a, indices(&sample_exact(std::io::Cursor::new(&input), 100, 7).unwrap()));
+    assert!(a.iter().any(|&i| i >= 500));
+    assert_eq!(indices(&sample_exact(std::io::Cursor::new(&input), 2000, 7).unwrap()).len(), 1000);
+
+    let n_records = DynamicFastXReader::from_file(&"tests/data/reads.fastq").unwrap().into_db().unwrap().sequence_count();
+    let db = sample_exact_from_file(&"tests/data/reads.fastq", 3, 1).unwrap();
+    assert_eq!(db.sequence_count(), 3.min(n_records));
+}